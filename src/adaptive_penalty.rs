@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use crate::cost_model::{CostModel, PenaltyKind};
+use crate::genalg::Genotype;
+
+/// Settings for raising and lowering the penalty weights to keep a share of the offspring feasible,
+/// see Vidal et al. "A hybrid genetic algorithm for multidepot and periodic vehicle routing problems".
+/// With soft time windows lateness, overtime and excess demand are charged by the cost functions of
+/// the cost model, those are adapted in place of the time window, return time and capacity penalties.
+#[derive(Debug, Clone)]
+pub struct AdaptivePenaltyConfig {
+    // the share of recent offspring that should be feasible in each constraint
+    pub target_feasible_ratio: f32,
+
+    // no change while the ratio is within the target +- the tolerance
+    pub tolerance: f32,
+
+    // the number of recent offspring the ratio is measured over
+    pub window: usize,
+
+    // iterations between the weight updates
+    pub update_every: usize,
+
+    pub increase: f32,
+    pub decrease: f32,
+
+    // limits on the weights relative to the weights the run started with
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl AdaptivePenaltyConfig {
+    /// The values used in the hgs papers
+    pub fn hgs() -> AdaptivePenaltyConfig {
+        return AdaptivePenaltyConfig {
+            target_feasible_ratio: 0.2,
+            tolerance: 0.05,
+            window: 100,
+            update_every: 100,
+            increase: 1.2,
+            decrease: 0.85,
+            min_scale: 0.01,
+            max_scale: 100.0,
+        };
+    }
+}
+
+/// The feasibility of the recent offspring and the current weights of one worker
+pub struct AdaptivePenalties {
+    config: AdaptivePenaltyConfig,
+    base: CostModel,
+
+    // per penalty kind, in the order of PenaltyKind::ALL
+    scales: Vec<f32>,
+    recent: Vec<VecDeque<bool>>,
+}
+
+impl AdaptivePenalties {
+    pub fn new(config: AdaptivePenaltyConfig, cost_model: &CostModel) -> AdaptivePenalties {
+        let num_kinds = PenaltyKind::ALL.len();
+        return AdaptivePenalties {
+            recent: vec![VecDeque::with_capacity(config.window); num_kinds],
+            scales: vec![1.0; num_kinds],
+            base: cost_model.clone(),
+            config,
+        };
+    }
+
+    /// Remember which constraints an evaluated offspring breaks
+    pub fn record(&mut self, genome: &Genotype) {
+        let counts = match genome.violation_counts {
+            Some(counts) => counts,
+            None => return,
+        };
+        for (idx, kind) in PenaltyKind::ALL.iter().enumerate() {
+            let recent = &mut self.recent[idx];
+            if recent.len() == self.config.window {
+                recent.pop_front();
+            }
+            recent.push_back(counts.get(*kind) == 0);
+        }
+    }
+
+    /// The share of the recent offspring without violations of the kind, None before any is recorded
+    pub fn feasible_ratio(&self, kind: PenaltyKind) -> Option<f32> {
+        let idx = PenaltyKind::ALL.iter().position(|k| *k == kind).unwrap();
+        let recent = &self.recent[idx];
+        if recent.is_empty() {
+            return None;
+        }
+        return Some(recent.iter().filter(|feasible| **feasible).count() as f32 / recent.len() as f32);
+    }
+
+    /// The cost model the run started with, its weights scaled by the current factors
+    pub fn cost_model(&self) -> CostModel {
+        let mut model = self.base.clone();
+        for (idx, kind) in PenaltyKind::ALL.iter().enumerate() {
+            *model.weight_mut(*kind) = self.base.weight(*kind).scaled(self.scales[idx]);
+        }
+        return model;
+    }
+
+    /// Raise the weights of the constraints too few offspring meet and lower the ones too many meet.
+    /// Only does something every update_every iterations, returns the new cost model when the
+    /// weights changed so the population can be evaluated again.
+    pub fn update(&mut self, iteration: usize) -> Option<CostModel> {
+        if iteration % self.config.update_every != 0 {
+            return None;
+        }
+        let target = self.config.target_feasible_ratio;
+        let mut changed = false;
+        for (idx, kind) in PenaltyKind::ALL.iter().enumerate() {
+            let ratio = match self.feasible_ratio(*kind) {
+                Some(ratio) => ratio,
+                None => continue,
+            };
+            let old_scale = self.scales[idx];
+            if ratio < target - self.config.tolerance {
+                self.scales[idx] = (old_scale * self.config.increase).min(self.config.max_scale);
+            } else if ratio > target + self.config.tolerance {
+                self.scales[idx] = (old_scale * self.config.decrease).max(self.config.min_scale);
+            }
+            changed |= self.scales[idx] != old_scale;
+        }
+        if !changed {
+            return None;
+        }
+        return Some(self.cost_model());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost_model::CostFunction;
+    use crate::genalg::{calculate_and_set_travel_time_multiple, generate_random_genome};
+    use crate::train_data_parsing::get_train_sett;
+
+    fn adapt_once(cost_model: CostModel) -> CostModel {
+        let mut env = get_train_sett(0);
+        env.cost_model = cost_model;
+        let mut adaptive = AdaptivePenalties::new(AdaptivePenaltyConfig::hgs(), &env.cost_model);
+        let mut population = generate_random_genome(&env, 50);
+        calculate_and_set_travel_time_multiple(&env, &mut population);
+        for genome in &population {
+            adaptive.record(genome);
+        }
+        return adaptive.update(100).unwrap();
+    }
+
+    #[test]
+    fn hard_windows_adapt_the_penalties() {
+        let hard = CostModel::hard();
+        let adapted = adapt_once(hard.clone());
+        assert_ne!(adapted.penalty.time_window, hard.penalty.time_window);
+        assert_eq!(adapted.lateness_cost, hard.lateness_cost);
+    }
+
+    #[test]
+    fn soft_windows_adapt_the_cost_functions() {
+        // random genomes are always late, so the lateness weight goes up
+        let soft = CostModel::soft(CostFunction::Linear(1.0), CostFunction::Linear(1.0), CostFunction::Linear(1.0));
+        let adapted = adapt_once(soft.clone());
+        assert_eq!(adapted.lateness_cost, CostFunction::Linear(1.2));
+        assert_eq!(adapted.penalty.time_window, soft.penalty.time_window);
+    }
+}
@@ -0,0 +1,36 @@
+/// A break every nurse with patients has to take, the whole break has to be inside the window
+#[derive(Debug, Clone, PartialEq)]
+pub struct LunchBreak {
+    pub start_time: i32,
+    pub end_time: i32,
+    pub duration: i32,
+}
+
+impl LunchBreak {
+    /// The last time the break can start and still end inside the window
+    pub fn latest_start(&self) -> f32 {
+        return (self.end_time - self.duration) as f32;
+    }
+}
+
+/// The labour rules for a nurse route, the evaluation places the breaks
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BreakRules {
+    pub lunch_break: Option<LunchBreak>,
+
+    // the most minutes of driving and care without a rest, waiting at least rest_duration minutes
+    // for a window to open and the lunch break both count as a rest
+    pub max_continuous_work: Option<i32>,
+    pub rest_duration: i32,
+}
+
+impl BreakRules {
+    /// No rules, the routes are evaluated as before
+    pub fn none() -> BreakRules {
+        return BreakRules::default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.lunch_break.is_none() && self.max_continuous_work.is_none();
+    }
+}
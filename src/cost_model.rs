@@ -0,0 +1,277 @@
+/// How the size of a violation is turned into a cost added to the travel time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CostFunction {
+    // the weight for any violation, whatever the size
+    Constant(f32),
+
+    // weight * amount
+    Linear(f32),
+
+    // weight * amount^2, punishes a few large violations harder than many small ones
+    Quadratic(f32),
+}
+
+impl CostFunction {
+    pub fn cost(&self, amount: f32) -> f32 {
+        if amount <= 0.0 {
+            return 0.0;
+        }
+        return match self {
+            CostFunction::Constant(weight) => *weight,
+            CostFunction::Linear(weight) => weight * amount,
+            CostFunction::Quadratic(weight) => weight * amount * amount,
+        };
+    }
+}
+
+impl CostFunction {
+    /// The same kind of function with the weight multiplied by the factor
+    pub fn scaled(&self, factor: f32) -> CostFunction {
+        return match self {
+            CostFunction::Constant(weight) => CostFunction::Constant(weight * factor),
+            CostFunction::Linear(weight) => CostFunction::Linear(weight * factor),
+            CostFunction::Quadratic(weight) => CostFunction::Quadratic(weight * factor),
+        };
+    }
+}
+
+/// How the summed penalties are applied to the cost of a genome
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PenaltyMode {
+    // cost * (1 + penalties)
+    Multiplicative,
+
+    // cost + penalties
+    Additive,
+}
+
+/// The kinds of violations that has their own penalty weight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PenaltyKind {
+    TimeWindow,
+    Capacity,
+    ReturnTime,
+    Skills,
+    Other,
+}
+
+impl PenaltyKind {
+    pub const ALL: [PenaltyKind; 5] = [
+        PenaltyKind::TimeWindow,
+        PenaltyKind::Capacity,
+        PenaltyKind::ReturnTime,
+        PenaltyKind::Skills,
+        PenaltyKind::Other,
+    ];
+}
+
+/// The penalty for each kind of violation, the functions get the size of the violation: minutes late
+/// for time windows, minutes of overtime for the return time and units above the capacity. The
+/// other kinds have size 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PenaltyModel {
+    pub mode: PenaltyMode,
+
+    pub time_window: CostFunction,
+    pub capacity: CostFunction,
+    pub return_time: CostFunction,
+    pub skills: CostFunction,
+
+    // breaks, synchronization, dropped mandatory patients and too many routes
+    pub other: CostFunction,
+}
+
+impl PenaltyModel {
+    /// Every violation adds 5% to the travel time, 10% for missing skills
+    pub fn multiplicative() -> PenaltyModel {
+        return PenaltyModel {
+            mode: PenaltyMode::Multiplicative,
+            time_window: CostFunction::Constant(0.05),
+            capacity: CostFunction::Constant(0.05),
+            return_time: CostFunction::Constant(0.05),
+            skills: CostFunction::Constant(0.1),
+            other: CostFunction::Constant(0.05),
+        };
+    }
+
+    pub fn additive(
+        time_window: CostFunction,
+        capacity: CostFunction,
+        return_time: CostFunction,
+        skills: CostFunction,
+        other: CostFunction,
+    ) -> PenaltyModel {
+        return PenaltyModel {
+            mode: PenaltyMode::Additive,
+            time_window,
+            capacity,
+            return_time,
+            skills,
+            other,
+        };
+    }
+
+    pub fn function(&self, kind: PenaltyKind) -> &CostFunction {
+        return match kind {
+            PenaltyKind::TimeWindow => &self.time_window,
+            PenaltyKind::Capacity => &self.capacity,
+            PenaltyKind::ReturnTime => &self.return_time,
+            PenaltyKind::Skills => &self.skills,
+            PenaltyKind::Other => &self.other,
+        };
+    }
+
+    pub fn function_mut(&mut self, kind: PenaltyKind) -> &mut CostFunction {
+        return match kind {
+            PenaltyKind::TimeWindow => &mut self.time_window,
+            PenaltyKind::Capacity => &mut self.capacity,
+            PenaltyKind::ReturnTime => &mut self.return_time,
+            PenaltyKind::Skills => &mut self.skills,
+            PenaltyKind::Other => &mut self.other,
+        };
+    }
+
+    /// Apply the summed penalties to the cost
+    pub fn apply(&self, cost: f32, penalty: f32) -> f32 {
+        return match self.mode {
+            PenaltyMode::Multiplicative => cost * (1.0 + penalty),
+            PenaltyMode::Additive => cost + penalty,
+        };
+    }
+}
+
+impl Default for PenaltyModel {
+    fn default() -> Self {
+        return PenaltyModel::multiplicative();
+    }
+}
+
+/// What the ga minimizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectiveMode {
+    // the penalized travel time
+    TravelTime,
+
+    // feasible genomes first, then the fewest nurses with patients, then the penalized travel time
+    FewestNurses,
+}
+
+/// How the evaluation treats broken time windows, overtime and overloaded nurses
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostModel {
+    // false: every violation multiplies the travel time with a flat penalty.
+    // true: the nurse does the care late and every minute late, minute of overtime past the return
+    // time and unit of demand above the capacity is charged with the cost functions below
+    pub soft_time_windows: bool,
+
+    pub lateness_cost: CostFunction,
+    pub overtime_cost: CostFunction,
+    pub excess_demand_cost: CostFunction,
+
+    // the penalties for violations that are not charged as a cost above
+    pub penalty: PenaltyModel,
+
+    pub objective: ObjectiveMode,
+}
+
+impl CostModel {
+    /// The hard window model the ga has always used
+    pub fn hard() -> CostModel {
+        return CostModel {
+            soft_time_windows: false,
+            lateness_cost: CostFunction::Linear(1.0),
+            overtime_cost: CostFunction::Linear(1.0),
+            excess_demand_cost: CostFunction::Linear(1.0),
+            penalty: PenaltyModel::default(),
+            objective: ObjectiveMode::TravelTime,
+        };
+    }
+
+    pub fn soft(lateness_cost: CostFunction, overtime_cost: CostFunction, excess_demand_cost: CostFunction) -> CostModel {
+        return CostModel {
+            soft_time_windows: true,
+            lateness_cost,
+            overtime_cost,
+            excess_demand_cost,
+            penalty: PenaltyModel::default(),
+            objective: ObjectiveMode::TravelTime,
+        };
+    }
+
+    /// Read a cost model from the command line: hard or soft windows, optionally followed by
+    /// ,additive and ,fewest-nurses in any order. None if it is not one
+    pub fn parse(spec: &str) -> Option<CostModel> {
+        let mut parts = spec.split(',');
+        let mut model = match parts.next()? {
+            "hard" => CostModel::hard(),
+            "soft" => CostModel::soft(CostFunction::Linear(1.0), CostFunction::Linear(1.0), CostFunction::Linear(1.0)),
+            _ => return None,
+        };
+        for part in parts {
+            match part {
+                // ten per minute late, minute of overtime and unit above the capacity, a hundred per
+                // missing skill and other violation
+                "additive" => {
+                    model.penalty = PenaltyModel::additive(
+                        CostFunction::Linear(10.0),
+                        CostFunction::Linear(10.0),
+                        CostFunction::Linear(10.0),
+                        CostFunction::Constant(100.0),
+                        CostFunction::Constant(100.0),
+                    )
+                }
+                "fewest-nurses" => model.objective = ObjectiveMode::FewestNurses,
+                _ => return None,
+            }
+        }
+        return Option::from(model);
+    }
+
+    /// The function that charges a violation of the kind: with soft time windows lateness, overtime
+    /// and excess demand are costs, everything else is a penalty
+    pub fn weight(&self, kind: PenaltyKind) -> &CostFunction {
+        return match (self.soft_time_windows, kind) {
+            (true, PenaltyKind::TimeWindow) => &self.lateness_cost,
+            (true, PenaltyKind::Capacity) => &self.excess_demand_cost,
+            (true, PenaltyKind::ReturnTime) => &self.overtime_cost,
+            _ => self.penalty.function(kind),
+        };
+    }
+
+    pub fn weight_mut(&mut self, kind: PenaltyKind) -> &mut CostFunction {
+        return match (self.soft_time_windows, kind) {
+            (true, PenaltyKind::TimeWindow) => &mut self.lateness_cost,
+            (true, PenaltyKind::Capacity) => &mut self.excess_demand_cost,
+            (true, PenaltyKind::ReturnTime) => &mut self.overtime_cost,
+            _ => self.penalty.function_mut(kind),
+        };
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        return CostModel::hard();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_models_parse_from_the_command_line() {
+        assert_eq!(CostModel::parse("hard"), Some(CostModel::hard()));
+
+        let model = CostModel::parse("soft,fewest-nurses,additive").unwrap();
+        assert!(model.soft_time_windows);
+        assert_eq!(model.objective, ObjectiveMode::FewestNurses);
+        assert_eq!(model.penalty.mode, PenaltyMode::Additive);
+
+        let model = CostModel::parse("hard,fewest-nurses").unwrap();
+        assert!(!model.soft_time_windows);
+        assert_eq!(model.penalty, PenaltyModel::multiplicative());
+
+        assert_eq!(CostModel::parse("soft,adaptive"), None);
+        assert_eq!(CostModel::parse("additive"), None);
+    }
+}
@@ -174,7 +174,16 @@ pub fn edge_crossover(parent1: &Genotype, parent2: &Genotype) -> Genotype {
     let mut child = Vec::new();
     child.push(NurseStop::Depot);
 
-    while  child.len() < parent1.stops.len(){
+    // partner visits are not picked from the edges, they keep their place from the first parent
+    let sync_partners: Vec<(usize, NurseStop)> = parent1
+        .stops
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, s)| matches!(s, NurseStop::SyncPartner(_)))
+        .collect();
+
+    while  child.len() < parent1.stops.len() - sync_partners.len(){
         let last = child.last().unwrap();
         let valid_next_opt = edge_map.get(last);
 
@@ -199,6 +208,9 @@ pub fn edge_crossover(parent1: &Genotype, parent2: &Genotype) -> Genotype {
                                     continue
                                 }
                             }
+                            NurseStop::SyncPartner(_) => {
+                                continue
+                            }
                         }
                         if v.len()< best_len{
                             best_k.insert(k);
@@ -259,11 +271,18 @@ pub fn edge_crossover(parent1: &Genotype, parent2: &Genotype) -> Genotype {
                             panic!("LLALALAWWAAAAA")
                         }
                     }
+                    NurseStop::SyncPartner(_) => {
+                        panic!("partner visits are never picked from the edges")
+                    }
                 }
             }
         }
     }
 
+    for (idx, partner) in sync_partners {
+        child.insert(idx.min(child.len()), partner);
+    }
+
     return Genotype::new(child,parent1.meta_genes.clone());
 
 }
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use crate::cost_model::{ObjectiveMode, PenaltyKind, PenaltyModel};
+use crate::fitness::Fitness;
+
+/// A broken constraint and how much it is broken by
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    // the care was done this many minutes after the last fitting window closed
+    TimeWindow { patient_id: i32, lateness: f32 },
+
+    // the nurse got back this many minutes after the return time
+    Overtime { minutes: f32 },
+
+    // the route carries this much more than the nurse capacity
+    Capacity { excess: i32 },
+
+    // the nurse lacks skills the patient needs
+    MissingSkills { patient_id: i32 },
+
+    // the lunch break started this many minutes after the latest start that fits in its window
+    LunchBreak { late_by: f32 },
+
+    // the nurse worked this many minutes past the limit without a rest
+    ContinuousWork { minutes: f32 },
+
+    // the two nurses at the patient did not start together, None when only one nurse came
+    Synchronization { patient_id: i32, gap: Option<f32> },
+
+    // a patient without a drop penalty was left out
+    MandatoryDropped { patient_id: i32 },
+
+    // the genome has more routes than there are nurses
+    TooManyRoutes { routes: usize, nurses: usize },
+}
+
+impl Violation {
+    /// The penalty weight the violation is charged with
+    pub fn penalty_kind(&self) -> PenaltyKind {
+        return match self {
+            Violation::TimeWindow { .. } => PenaltyKind::TimeWindow,
+            Violation::Overtime { .. } => PenaltyKind::ReturnTime,
+            Violation::Capacity { .. } => PenaltyKind::Capacity,
+            Violation::MissingSkills { .. } => PenaltyKind::Skills,
+            _ => PenaltyKind::Other,
+        };
+    }
+}
+
+/// How many violations of each penalty kind a genome has
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ViolationCounts {
+    pub time_window: i32,
+    pub capacity: i32,
+    pub return_time: i32,
+    pub skills: i32,
+    pub other: i32,
+}
+
+impl ViolationCounts {
+    pub fn get(&self, kind: PenaltyKind) -> i32 {
+        return match kind {
+            PenaltyKind::TimeWindow => self.time_window,
+            PenaltyKind::Capacity => self.capacity,
+            PenaltyKind::ReturnTime => self.return_time,
+            PenaltyKind::Skills => self.skills,
+            PenaltyKind::Other => self.other,
+        };
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return match self {
+            Violation::TimeWindow { patient_id, lateness } => {
+                write!(f, "patient {} cared for {:.2} minutes late", patient_id, lateness)
+            }
+            Violation::Overtime { minutes } => write!(f, "back {:.2} minutes after the return time", minutes),
+            Violation::Capacity { excess } => write!(f, "{} above the capacity", excess),
+            Violation::MissingSkills { patient_id } => write!(f, "not qualified for patient {}", patient_id),
+            Violation::LunchBreak { late_by } => write!(f, "lunch break {:.2} minutes too late", late_by),
+            Violation::ContinuousWork { minutes } => {
+                write!(f, "worked {:.2} minutes past the limit without a rest", minutes)
+            }
+            Violation::Synchronization { patient_id, gap: Some(gap) } => {
+                write!(f, "the nurses at patient {} started {:.2} minutes apart", patient_id, gap)
+            }
+            Violation::Synchronization { patient_id, gap: None } => {
+                write!(f, "patient {} did not get two different nurses", patient_id)
+            }
+            Violation::MandatoryDropped { patient_id } => write!(f, "patient {} has to be visited", patient_id),
+            Violation::TooManyRoutes { routes, nurses } => write!(f, "{} routes for {} nurses", routes, nurses),
+        };
+    }
+}
+
+/// What happened on a single route, route k is driven by nurse k
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteReport {
+    pub nurse_idx: usize,
+    pub num_visits: usize,
+
+    pub travel_time: f32,
+    pub care_time: f32,
+
+    // time spent waiting for windows to open and for the second nurse at synchronized visits
+    pub waiting_time: f32,
+
+    // the time the nurse is back at the depot
+    pub return_time: f32,
+    pub load: i32,
+
+    pub violations: Vec<Violation>,
+}
+
+impl RouteReport {
+    pub fn is_valid(&self) -> bool {
+        return self.violations.is_empty();
+    }
+}
+
+/// The full evaluation of a genome, the scalar fitness is derived from this
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationReport {
+    pub routes: Vec<Rc<RouteReport>>,
+
+    pub dropped_patients: Vec<i32>,
+    pub dropped_cost: f32,
+
+    // violations that does not belong to a single route
+    pub violations: Vec<Violation>,
+
+    // travel time plus soft violation and drop costs
+    pub cost: f32,
+
+    // the summed penalties, applied to the cost by the penalty model
+    pub penalty: f32,
+    pub penalty_model: PenaltyModel,
+}
+
+impl EvaluationReport {
+    /// The penalized travel time stored on the genotype
+    pub fn fitness(&self) -> f32 {
+        return self.penalty_model.apply(self.cost, self.penalty);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        return self.violations.is_empty() && self.routes.iter().all(|r| r.is_valid());
+    }
+
+    /// Routes with at least one patient, empty routes do not need a nurse
+    pub fn nurses_used(&self) -> usize {
+        return self.routes.iter().filter(|r| r.num_visits > 0).count();
+    }
+
+    /// The fitness genomes are compared by under the objective
+    pub fn comparable_fitness(&self, objective: ObjectiveMode) -> Fitness {
+        return Fitness {
+            objective,
+            valid: self.is_valid(),
+            nurses_used: self.nurses_used(),
+            travel_time: self.fitness(),
+        };
+    }
+
+    pub fn total_travel_time(&self) -> f32 {
+        return self.routes.iter().map(|r| r.travel_time).sum();
+    }
+
+    /// Every violation together with the route it happened on
+    pub fn all_violations(&self) -> Vec<(Option<usize>, &Violation)> {
+        let mut all: Vec<(Option<usize>, &Violation)> = self
+            .routes
+            .iter()
+            .flat_map(|r| r.violations.iter().map(move |v| (Some(r.nurse_idx), v)))
+            .collect();
+        all.extend(self.violations.iter().map(|v| (None, v)));
+        return all;
+    }
+
+    pub fn total_lateness(&self) -> f32 {
+        return self
+            .all_violations()
+            .iter()
+            .map(|(_, v)| match v {
+                Violation::TimeWindow { lateness, .. } => *lateness,
+                _ => 0.0,
+            })
+            .fold(0.0, |total, lateness| total + lateness);
+    }
+
+    pub fn violation_counts(&self) -> ViolationCounts {
+        let mut counts = ViolationCounts::default();
+        for (_, violation) in self.all_violations() {
+            match violation.penalty_kind() {
+                PenaltyKind::TimeWindow => counts.time_window += 1,
+                PenaltyKind::Capacity => counts.capacity += 1,
+                PenaltyKind::ReturnTime => counts.return_time += 1,
+                PenaltyKind::Skills => counts.skills += 1,
+                PenaltyKind::Other => counts.other += 1,
+            }
+        }
+        return counts;
+    }
+
+    pub fn break_violations(&self) -> i32 {
+        return self
+            .all_violations()
+            .iter()
+            .filter(|(_, v)| matches!(v, Violation::LunchBreak { .. } | Violation::ContinuousWork { .. }))
+            .count() as i32;
+    }
+}
+
+impl Display for EvaluationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "fitness {:.2}, travel time {:.2}, penalty {:.2}, valid: {}",
+            self.fitness(),
+            self.total_travel_time(),
+            self.penalty,
+            self.is_valid()
+        )?;
+        for route in &self.routes {
+            if route.num_visits == 0 && route.is_valid() {
+                continue;
+            }
+            writeln!(
+                f,
+                "  nurse {:>3}: {:>3} visits, travel {:>8.2}, care {:>8.2}, waiting {:>8.2}, back at {:>8.2}, load {:>4}",
+                route.nurse_idx,
+                route.num_visits,
+                route.travel_time,
+                route.care_time,
+                route.waiting_time,
+                route.return_time,
+                route.load
+            )?;
+            for violation in &route.violations {
+                writeln!(f, "      {}", violation)?;
+            }
+        }
+        if !self.dropped_patients.is_empty() {
+            writeln!(f, "  dropped {:?}, cost {:.2}", self.dropped_patients, self.dropped_cost)?;
+        }
+        for violation in &self.violations {
+            writeln!(f, "  {}", violation)?;
+        }
+        return Ok(());
+    }
+}
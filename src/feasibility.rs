@@ -0,0 +1,355 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+use crate::train_data_parsing::{EnvPruned, PatientPruned};
+
+/// Why a single patient can never be served, no matter how the routes are built
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatientIssue {
+    // the care time does not fit inside any of the time windows
+    CareLongerThanWindow { care_time: i32, window: i32 },
+
+    // driving straight from the depot arrives too late to finish the care in any window
+    WindowUnreachable { arrival: f32, end_time: i32 },
+
+    // the care can be done but no nurse can get back before their shift ends
+    CannotReturnInTime { earliest_return: f32, return_time: i32 },
+
+    // the patient alone needs more than any nurse can handle
+    DemandAboveCapacity { demand: i32, capacity: i32 },
+
+    // no nurse has every skill the patient needs
+    NoQualifiedNurse { missing_skills: Vec<String> },
+
+    // the patient needs two nurses at once but only one has the skills
+    SingleQualifiedNurse,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeasibilityReport {
+    pub set_name: String,
+    pub number_nurses: i32,
+
+    pub patient_issues: Vec<(i32, PatientIssue)>,
+
+    // patients with a drop penalty, they are left out of the bounds and their issues only mean they
+    // will be dropped
+    pub optional_patients: Vec<i32>,
+
+    // demand of the patients that have to be visited
+    pub total_demand: i32,
+
+    // the minimum number of nurses needed to carry the total demand
+    pub nurse_bound_demand: i32,
+
+    // the size of a set of patients where no two can share a nurse because of their time windows
+    pub nurse_bound_time_windows: i32,
+    pub conflicting_patients: Vec<i32>,
+}
+
+impl FeasibilityReport {
+    /// Largest of the lower bounds on the number of nurses
+    pub fn nurse_lower_bound(&self) -> i32 {
+        return self.nurse_bound_demand.max(self.nurse_bound_time_windows);
+    }
+
+    /// False if the instance is proven impossible, true does not guarantee a solution exists
+    pub fn possibly_feasible(&self) -> bool {
+        let mandatory_issues = self
+            .patient_issues
+            .iter()
+            .any(|(patient_id, _)| !self.optional_patients.contains(patient_id));
+        return !mandatory_issues && self.nurse_lower_bound() <= self.number_nurses;
+    }
+}
+
+impl Display for FeasibilityReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "feasibility report for {}", self.set_name)?;
+        writeln!(
+            f,
+            "  possibly feasible: {}",
+            if self.possibly_feasible() { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "  nurses available: {}, lower bound: {} (demand: {}, time windows: {})",
+            self.number_nurses,
+            self.nurse_lower_bound(),
+            self.nurse_bound_demand,
+            self.nurse_bound_time_windows
+        )?;
+        if self.nurse_bound_time_windows > 1 {
+            writeln!(f, "  patients that each need their own nurse: {:?}", self.conflicting_patients)?;
+        }
+        writeln!(f, "  total demand: {}", self.total_demand)?;
+        if !self.optional_patients.is_empty() {
+            writeln!(f, "  optional patients: {}", self.optional_patients.len())?;
+        }
+        writeln!(f, "  patients that can not be served: {}", self.patient_issues.len())?;
+        for (patient_id, issue) in &self.patient_issues {
+            match issue {
+                PatientIssue::CareLongerThanWindow { care_time, window } => writeln!(
+                    f,
+                    "    patient {:>4}: care time {} is longer than the longest window {}",
+                    patient_id, care_time, window
+                )?,
+                PatientIssue::WindowUnreachable { arrival, end_time } => writeln!(
+                    f,
+                    "    patient {:>4}: earliest arrival {:.2} is too late to finish the care before {}",
+                    patient_id, arrival, end_time
+                )?,
+                PatientIssue::CannotReturnInTime { earliest_return, return_time } => writeln!(
+                    f,
+                    "    patient {:>4}: earliest return {:.2} is after the latest return time {}",
+                    patient_id, earliest_return, return_time
+                )?,
+                PatientIssue::DemandAboveCapacity { demand, capacity } => writeln!(
+                    f,
+                    "    patient {:>4}: demand {} is above the nurse capacity {}",
+                    patient_id, demand, capacity
+                )?,
+                PatientIssue::NoQualifiedNurse { missing_skills } => writeln!(
+                    f,
+                    "    patient {:>4}: no nurse has the skills {:?}",
+                    patient_id, missing_skills
+                )?,
+                PatientIssue::SingleQualifiedNurse => writeln!(
+                    f,
+                    "    patient {:>4}: needs two nurses but only one has the skills",
+                    patient_id
+                )?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// The loosest limits found anywhere in the fleet, a patient that breaks these can not be served by
+/// any nurse
+struct FleetLimits {
+    earliest_start: i32,
+    latest_return: i32,
+    max_capacity: i32,
+
+    // travel indexes of the depots that has at least one nurse
+    used_depots: Vec<i32>,
+}
+
+impl FleetLimits {
+    fn new(env: &EnvPruned) -> FleetLimits {
+        let mut used_depots: Vec<i32> = env
+            .nurses
+            .iter()
+            .map(|n| env.depots[n.depot].travel_index as i32)
+            .collect();
+        used_depots.sort();
+        used_depots.dedup();
+
+        return FleetLimits {
+            earliest_start: env.nurses.iter().map(|n| n.start_time).min().unwrap_or(0),
+            latest_return: env.nurses.iter().map(|n| n.return_time).max().unwrap_or(env.depo_ret_time),
+            max_capacity: env.nurses.iter().map(|n| n.capacity).max().unwrap_or(env.capacity_nurse),
+            used_depots,
+        };
+    }
+
+    fn min_travel_from_depot(&self, env: &EnvPruned, patient_id: i32) -> f32 {
+        return self
+            .used_depots
+            .iter()
+            .map(|d| env.get_min_travel_time_between(d, &patient_id))
+            .fold(f32::INFINITY, f32::min);
+    }
+
+    fn min_travel_to_depot(&self, env: &EnvPruned, patient_id: i32) -> f32 {
+        return self
+            .used_depots
+            .iter()
+            .map(|d| env.get_min_travel_time_between(&patient_id, d))
+            .fold(f32::INFINITY, f32::min);
+    }
+}
+
+fn earliest_arrival_from_depot(env: &EnvPruned, limits: &FleetLimits, patient_id: i32) -> f32 {
+    return limits.earliest_start as f32 + limits.min_travel_from_depot(env, patient_id);
+}
+
+/// The time the care at the patient is done if the nurse drives straight there from the closest
+/// depot, None if no time window can be reached
+fn earliest_finish_from_depot(env: &EnvPruned, limits: &FleetLimits, patient_id: i32, patient: &PatientPruned) -> Option<f32> {
+    let arrival = earliest_arrival_from_depot(env, limits, patient_id);
+    return patient
+        .earliest_care_start(arrival)
+        .map(|care_start| care_start + patient.care_time as f32);
+}
+
+/// Check if one nurse can visit the first patient and then the second one
+fn can_follow(env: &EnvPruned, limits: &FleetLimits, first_id: i32, second_id: i32) -> bool {
+    let first = env.patients.get((first_id - 1) as usize).unwrap();
+    let second = env.patients.get((second_id - 1) as usize).unwrap();
+
+    let first_done = match earliest_finish_from_depot(env, limits, first_id, first) {
+        Some(t) => t,
+        None => return false,
+    };
+    let arrival = first_done + env.get_min_travel_time_between(&first_id, &second_id);
+    let second_done = match second.earliest_care_start(arrival) {
+        Some(t) => t + second.care_time as f32,
+        None => return false,
+    };
+
+    return second_done + limits.min_travel_to_depot(env, second_id) <= limits.latest_return as f32;
+}
+
+/// Greedy clique in the graph where an edge means two patients can not share a nurse in any order.
+/// Every patient in the clique needs its own nurse so the size is a lower bound on the fleet size
+fn time_window_conflict_clique(env: &EnvPruned, limits: &FleetLimits) -> Vec<i32> {
+    let num_patients = env.patients.len();
+    // optional patients can be dropped so they never force an extra nurse
+    let mandatory = |idx: usize| env.patients[idx].drop_penalty.is_none();
+
+    let mut conflicts: Vec<Vec<bool>> = vec![vec![false; num_patients]; num_patients];
+    let mut degree: Vec<usize> = vec![0; num_patients];
+    for a in 0..num_patients {
+        for b in (a + 1)..num_patients {
+            if !mandatory(a) || !mandatory(b) {
+                continue;
+            }
+            let a_id = (a + 1) as i32;
+            let b_id = (b + 1) as i32;
+            if !can_follow(env, limits, a_id, b_id) && !can_follow(env, limits, b_id, a_id) {
+                conflicts[a][b] = true;
+                conflicts[b][a] = true;
+                degree[a] += 1;
+                degree[b] += 1;
+            }
+        }
+    }
+
+    // try to grow a clique from the highest degree patients first
+    let mut order: Vec<usize> = (0..num_patients).filter(|idx| mandatory(*idx)).collect();
+    order.sort_by(|a, b| degree[*b].cmp(&degree[*a]));
+
+    let mut best: Vec<usize> = Vec::new();
+    for start in &order {
+        if degree[*start] < best.len() {
+            break;
+        }
+        let mut clique = vec![*start];
+        for cand in &order {
+            if clique.iter().all(|member| conflicts[*member][*cand]) {
+                clique.push(*cand);
+            }
+        }
+        if clique.len() > best.len() {
+            best = clique;
+        }
+    }
+
+    let mut ids: Vec<i32> = best.iter().map(|idx| (*idx + 1) as i32).collect();
+    ids.sort();
+    return ids;
+}
+
+/// Check if the instance can be solved at all before spending time on a ga run
+pub fn analyse_feasibility(env: &EnvPruned) -> FeasibilityReport {
+    let limits = FleetLimits::new(env);
+    let mut patient_issues = Vec::new();
+    let mut optional_patients = Vec::new();
+    let mut total_demand = 0;
+
+    for (idx, patient) in env.patients.iter().enumerate() {
+        let patient_id = (idx + 1) as i32;
+        if patient.drop_penalty.is_some() {
+            optional_patients.push(patient_id);
+        } else {
+            total_demand += patient.demand;
+        }
+
+        let window = patient.time_windows.iter().map(|(start, end)| end - start).max().unwrap_or(0);
+        let earliest_finish = earliest_finish_from_depot(env, &limits, patient_id, patient);
+
+        if patient.care_time > window {
+            patient_issues.push((
+                patient_id,
+                PatientIssue::CareLongerThanWindow { care_time: patient.care_time, window },
+            ));
+        } else if earliest_finish.is_none() {
+            patient_issues.push((
+                patient_id,
+                PatientIssue::WindowUnreachable {
+                    arrival: earliest_arrival_from_depot(env, &limits, patient_id),
+                    end_time: patient.end_time,
+                },
+            ));
+        } else {
+            let earliest_return = earliest_finish.unwrap() + limits.min_travel_to_depot(env, patient_id);
+            if earliest_return > limits.latest_return as f32 {
+                patient_issues.push((
+                    patient_id,
+                    PatientIssue::CannotReturnInTime { earliest_return, return_time: limits.latest_return },
+                ));
+            }
+        }
+
+        if patient.demand > limits.max_capacity {
+            patient_issues.push((
+                patient_id,
+                PatientIssue::DemandAboveCapacity { demand: patient.demand, capacity: limits.max_capacity },
+            ));
+        }
+
+        let qualified_nurses = (0..env.nurses.len()).filter(|k| env.nurse_can_serve(*k, patient_id)).count();
+        if patient.synchronized && qualified_nurses == 1 {
+            patient_issues.push((patient_id, PatientIssue::SingleQualifiedNurse));
+        }
+        if qualified_nurses == 0 {
+            // report the skills missing on the nurse that comes closest
+            let missing = env
+                .nurses
+                .iter()
+                .map(|n| patient.required_skills & !n.skills)
+                .min_by_key(|m| m.count_ones())
+                .unwrap_or(patient.required_skills);
+            let missing_skills = env
+                .skill_names
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| missing & (1 << bit) != 0)
+                .map(|(_, name)| name.clone())
+                .collect();
+            patient_issues.push((patient_id, PatientIssue::NoQualifiedNurse { missing_skills }));
+        }
+    }
+
+    // the fewest nurses that together can carry the total demand, taking the largest first
+    let mut capacities: Vec<i32> = env.nurses.iter().map(|n| n.capacity).collect();
+    capacities.sort_by(|a, b| b.cmp(a));
+    let mut nurse_bound_demand = 0;
+    let mut carried = 0;
+    for capacity in &capacities {
+        if carried >= total_demand {
+            break;
+        }
+        carried += capacity;
+        nurse_bound_demand += 1;
+    }
+    if carried < total_demand {
+        // not even the whole fleet can carry the demand
+        nurse_bound_demand = env.number_nurses + 1;
+    }
+
+    let conflicting_patients = time_window_conflict_clique(env, &limits);
+
+    return FeasibilityReport {
+        set_name: env.set_name.clone(),
+        number_nurses: env.number_nurses,
+        patient_issues,
+        optional_patients,
+        total_demand,
+        nurse_bound_demand,
+        nurse_bound_time_windows: conflicting_patients.len() as i32,
+        conflicting_patients,
+    };
+}
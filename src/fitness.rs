@@ -0,0 +1,65 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::cost_model::ObjectiveMode;
+
+/// The comparable fitness of a genome, the smaller one is the better. Travel times are compared
+/// with the IEEE total order so NaN sorts after every number instead of panicking.
+#[derive(Debug, Clone, Copy)]
+pub struct Fitness {
+    pub objective: ObjectiveMode,
+    pub valid: bool,
+
+    // routes with at least one patient
+    pub nurses_used: usize,
+
+    // the penalized travel time
+    pub travel_time: f32,
+}
+
+impl Fitness {
+    /// The values compared, in order of importance
+    fn key(&self) -> (bool, usize) {
+        return match self.objective {
+            ObjectiveMode::TravelTime => (false, 0),
+            ObjectiveMode::FewestNurses => (!self.valid, self.nurses_used),
+        };
+    }
+}
+
+impl PartialEq for Fitness {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Fitness {}
+
+impl PartialOrd for Fitness {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Fitness {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self
+            .key()
+            .cmp(&other.key())
+            .then_with(|| self.travel_time.total_cmp(&other.travel_time));
+    }
+}
+
+impl Display for Fitness {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return match self.objective {
+            ObjectiveMode::TravelTime => write!(f, "{:.3}", self.travel_time),
+            ObjectiveMode::FewestNurses => write!(
+                f,
+                "valid {}, {} nurses, {:.3}",
+                self.valid, self.nurses_used, self.travel_time
+            ),
+        };
+    }
+}
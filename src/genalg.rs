@@ -1,23 +1,43 @@
 use core::option::Option;
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use rand::{
     Rng,
 };
 use rand::seq::SliceRandom;
-use crate::mutation::MetaGenes;
+use crate::evaluation::{EvaluationReport, RouteReport, Violation, ViolationCounts};
+use crate::fitness::Fitness;
+use crate::mutation::{repair_skill_mismatch, MetaGenes};
+use crate::route_cache::{with_thread_cache, RouteCache};
 
-use crate::train_data_parsing::{EnvPruned, PatientPruned};
+use crate::train_data_parsing::{EnvPruned, NursePruned, PatientPruned};
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Eq,Hash)]
 pub enum NurseStop {
     // The patient id
     Patient(i32),
 
+    // the second nurse at a patient that needs two nurses at once, placed on another route than
+    // the Patient stop with the same id
+    SyncPartner(i32),
+
     // if the
     Depot,
 }
 
+impl NurseStop {
+    /// The patient visited at the stop, None for depots
+    pub fn patient_id(&self) -> Option<i32> {
+        return match self {
+            NurseStop::Patient(patient_id) => Option::from(*patient_id),
+            NurseStop::SyncPartner(patient_id) => Option::from(*patient_id),
+            NurseStop::Depot => Option::None,
+        };
+    }
+}
+
 
 
 #[derive(Debug, Clone)]
@@ -27,6 +47,17 @@ pub struct Genotype {
 
     pub travel_time: Option<f32>,
     pub valid: Option<bool>,
+
+    // minutes the care was done (or would have been done) after the patients windows closed
+    pub lateness: Option<f32>,
+
+    // lunch breaks and rests that could not be placed within the break rules
+    pub break_violations: Option<i32>,
+
+    pub violation_counts: Option<ViolationCounts>,
+
+    // what the genomes are sorted by, follows the objective of the cost model
+    pub fitness: Option<Fitness>,
 }
 
 
@@ -39,13 +70,17 @@ impl Genotype {
             stops: nurse_stop,
             travel_time: Option::None,
             valid: Option::None,
+            lateness: Option::None,
+            break_violations: Option::None,
+            violation_counts: Option::None,
+            fitness: Option::None,
         };
     }
     pub fn get_as_word(&self) -> String {
         let mut word = String::with_capacity(self.stops.len());
         for s in &self.stops {
             match s {
-                NurseStop::Patient(p_num) => {
+                NurseStop::Patient(p_num) | NurseStop::SyncPartner(p_num) => {
                     word.push_str(&*p_num.to_string());
                     word.push("-".parse().unwrap());
                 }
@@ -65,12 +100,23 @@ impl Genotype {
         return word;
     }
 
-    pub fn get_as_delivery_str(&self) -> Vec<Vec<i32>>{
+    /// The patient ids of every route, route k is driven by nurse k. Dropped patients are left out,
+    /// see [Genotype::get_dropped_patients]
+    pub fn get_as_delivery_str(&self, env: &EnvPruned) -> Vec<Vec<i32>>{
+        let mut routes = self.get_segments();
+        if env.allows_dropped_patients() {
+            routes.truncate(env.nurses.len());
+        }
+        return routes;
+    }
+
+    /// The patient ids between every pair of depot markers, including the unassigned segment
+    fn get_segments(&self) -> Vec<Vec<i32>>{
         let mut res = Vec::new();
         let mut tmp_vec = Vec::new();
         for s in &self.stops{
             match s {
-                NurseStop::Patient(p_num) => {
+                NurseStop::Patient(p_num) | NurseStop::SyncPartner(p_num) => {
                     tmp_vec.push(p_num.clone());
                 }
                 NurseStop::Depot => {
@@ -84,6 +130,40 @@ impl Genotype {
         // println!("{:?}", res)
         return res;
     }
+
+    /// Same as the delivery string but every route is paired with the depot its nurse starts from,
+    /// dropped patients are left out
+    pub fn get_as_depot_delivery_str(&self, env: &EnvPruned) -> Vec<(usize, Vec<i32>)> {
+        return self
+            .get_segments()
+            .into_iter()
+            .take(env.nurses.len())
+            .enumerate()
+            .map(|(nurse_idx, route)| {
+                let depot = match env.nurses.get(nurse_idx) {
+                    Some(nurse) => nurse.depot,
+                    None => 0,
+                };
+                (depot, route)
+            })
+            .collect();
+    }
+}
+
+impl Genotype {
+    /// The patients in the unassigned segment after the last nurse, always empty unless the instance
+    /// has optional patients
+    pub fn get_dropped_patients(&self, env: &EnvPruned) -> Vec<i32> {
+        if !env.allows_dropped_patients() {
+            return Vec::new();
+        }
+        return self
+            .get_segments()
+            .into_iter()
+            .skip(env.nurses.len())
+            .flatten()
+            .collect();
+    }
 }
 
 impl Eq for Genotype {}
@@ -96,18 +176,19 @@ impl PartialEq<Self> for Genotype {
 
 impl PartialOrd<Self> for Genotype {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.travel_time
-            .unwrap()
-            .partial_cmp(&other.travel_time.unwrap())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Genotype {
+    /// Better fitness first, genomes that are not evaluated yet go last
     fn cmp(&self, other: &Self) -> Ordering {
-        self.travel_time
-            .unwrap()
-            .partial_cmp(&other.travel_time.unwrap())
-            .unwrap()
+        match (&self.fitness, &other.fitness) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
     }
 }
 
@@ -123,15 +204,8 @@ pub fn calculate_pop_diversity(population: &Vec<Genotype>, env: &EnvPruned) -> f
     for gno in population {
         let mut last = &NurseStop::Depot;
         for g in &gno.stops {
-            let x1 = match *last {
-                NurseStop::Patient(n) => n,
-                NurseStop::Depot => 0,
-            };
-
-            let x2 = match *g {
-                NurseStop::Patient(n) => n,
-                NurseStop::Depot => 0,
-            };
+            let x1 = last.patient_id().unwrap_or(0);
+            let x2 = g.patient_id().unwrap_or(0);
             last = g;
 
             // println!("{:?}", x1);
@@ -143,10 +217,7 @@ pub fn calculate_pop_diversity(population: &Vec<Genotype>, env: &EnvPruned) -> f
                 .unwrap() += 1;
         }
         // to the last
-        let x1 = match *last {
-            NurseStop::Patient(n) => n,
-            NurseStop::Depot => 0,
-        };
+        let x1 = last.patient_id().unwrap_or(0);
         *count
             .get_mut(x1 as usize)
             .unwrap()
@@ -180,108 +251,544 @@ pub fn calculate_pop_diversity(population: &Vec<Genotype>, env: &EnvPruned) -> f
     // println!("{:?}", roll_entropy);
 }
 
-pub fn calculate_and_set_travel_time(env: &EnvPruned, genotype: &mut Genotype) {
-    let penalty_add = 0.05;
-
-    let mut total_travel_time: f32 = 0.0;
+/// A route report together with what the scalar fitness needs from it
+#[derive(Debug, Clone)]
+pub struct RouteEvaluation {
+    // shared with the evaluation reports so a cached route is not copied
+    pub report: Rc<RouteReport>,
 
-    let mut penalty: f32 = 1.0;
+    // soft lateness, overtime and capacity costs
+    pub violation_cost: f32,
 
-    let nurse_capacity = env.capacity_nurse;
+    // added to the penalty factor of the genome
+    pub penalty: f32,
 
-    let mut valid: bool = true;
-    // current nurse
-    let mut nurse_time: f32 = 0.0;
-    let mut prev_stop_id: i32 = 0;
-    let mut nurse_strain: i32 = 0;
+    // (patient id, route, care start) for every visit to a patient that needs two nurses
+    pub sync_visits: Vec<(i32, usize, f32)>,
+}
 
-    for stop in &genotype.stops {
-        match stop {
-            NurseStop::Patient(patient_id) => {
-                // get the patient
-                let patient: &PatientPruned = env.patients.get((patient_id - 1) as usize).unwrap();
+/// The travel time for the next leg, the nurse rests before leaving if the leg and the work after it
+/// would go past the maximum continuous working time
+fn travel_with_rest(
+    env: &EnvPruned,
+    from: &i32,
+    to: &i32,
+    work_after: f32,
+    nurse_time: &mut f32,
+    continuous_work: &mut f32,
+) -> f32 {
+    let mut travel_time = env.get_travel_time_between_at(from, to, *nurse_time);
+    if let Some(max_work) = env.break_rules.max_continuous_work {
+        if *continuous_work > 0.0 && *continuous_work + travel_time + work_after > max_work as f32 {
+            *nurse_time += env.break_rules.rest_duration as f32;
+            *continuous_work = 0.0;
+            travel_time = env.get_travel_time_between_at(from, to, *nurse_time);
+        }
+    }
+    return travel_time;
+}
 
-                // find the travel time
-                let travel_to_time = env.get_travel_time_between(&prev_stop_id, &patient_id);
-                // println!("{}",travel_to_time);
+/// Drive route k with nurse k. When lunch_before is set the lunch break is taken before driving to
+/// that stop, the length of the route means before driving home.
+fn drive_route(
+    env: &EnvPruned,
+    nurse_idx: usize,
+    route: &[NurseStop],
+    sync_starts: &HashMap<i32, f32>,
+    lunch_before: Option<usize>,
+) -> RouteEvaluation {
+    // the soft costs are only used with soft time windows
+    let cost_model = &env.cost_model;
+    let penalties = &cost_model.penalty;
+
+    let mut eval = RouteEvaluation {
+        report: Rc::new(RouteReport {
+            nurse_idx,
+            num_visits: route.len(),
+            travel_time: 0.0,
+            care_time: 0.0,
+            waiting_time: 0.0,
+            return_time: 0.0,
+            load: 0,
+            violations: Vec::new(),
+        }),
+        violation_cost: 0.0,
+        penalty: 0.0,
+        sync_visits: Vec::new(),
+    };
+    let report = Rc::get_mut(&mut eval.report).unwrap();
+
+    // more routes than nurses are driven by the last nurse and flagged by the caller
+    let nurse: &NursePruned = env.nurses.get(nurse_idx).unwrap_or(env.nurses.last().unwrap());
+    let depot_id: i32 = env.get_nurse_depot_index(nurse_idx);
+    let mut nurse_time: f32 = nurse.start_time as f32;
+    let mut prev_stop_id: i32 = depot_id;
+
+    // minutes of driving and care since the last rest
+    let mut continuous_work: f32 = 0.0;
+
+    for (pos, stop) in route.iter().enumerate() {
+        // routes are split on the depots so every stop is a patient
+        let patient_id = stop.patient_id().unwrap();
+        let patient: &PatientPruned = env.patients.get((patient_id - 1) as usize).unwrap();
+        // the partner visit shares the care but the demand is counted once, see [finish_visit]
+        let is_partner = matches!(stop, NurseStop::SyncPartner(_));
+
+        if lunch_before == Some(pos) {
+            take_lunch(env, report, &mut nurse_time, &mut continuous_work);
+        }
 
-                total_travel_time += travel_to_time;
-                nurse_time += travel_to_time;
+        // find the travel time
+        let travel_to_time = travel_with_rest(
+            env,
+            &prev_stop_id,
+            &patient_id,
+            patient.care_time as f32,
+            &mut nurse_time,
+            &mut continuous_work,
+        );
+
+        report.travel_time += travel_to_time;
+        nurse_time += travel_to_time;
+        continuous_work += travel_to_time;
+        let arrival = nurse_time;
+
+        if patient.synchronized {
+            // wait for the other nurse
+            match sync_starts.get(&patient_id) {
+                Some(sync_start) if nurse_time < *sync_start => nurse_time = *sync_start,
+                _ => {}
+            }
+        }
 
-                // check care time and wait if neccecery
-                if nurse_time < patient.start_time as f32 {
-                    // the nurse has to wait until the start time
-                    nurse_time = patient.start_time as f32;
+        // check care time and wait for the next window the care fits in if neccecery
+        let care_start = match patient.earliest_care_start(nurse_time) {
+            Some(care_start) => care_start,
+            None => {
+                // the stop is invalid
+                let (care_start, lateness) = patient.least_late_care_start(nurse_time);
+                report.violations.push(Violation::TimeWindow { patient_id, lateness });
+
+                if cost_model.soft_time_windows {
+                    // do the care late and pay for every minute
+                    eval.violation_cost += cost_model.lateness_cost.cost(lateness);
+                    care_start
+                } else {
+                    eval.penalty += penalties.time_window.cost(lateness);
+                    // the nurse has to wait until the start time and the care is not counted
+                    let care_start = nurse_time.max(patient.start_time as f32);
+                    if patient.synchronized {
+                        eval.sync_visits.push((patient_id, nurse_idx, care_start));
+                    }
+                    report.waiting_time += care_start - arrival;
+                    nurse_time = care_start;
+                    continuous_work = check_continuous_work(env, report, continuous_work, care_start - arrival, 0.0);
+                    prev_stop_id = patient_id;
+                    finish_visit(env, &mut eval.penalty, report, nurse_idx, patient_id, patient, is_partner);
+                    continue;
                 }
+            }
+        };
+        if patient.synchronized {
+            eval.sync_visits.push((patient_id, nurse_idx, care_start));
+        }
+        report.waiting_time += care_start - arrival;
+        report.care_time += patient.care_time as f32;
+        nurse_time = care_start + patient.care_time as f32;
+        continuous_work = check_continuous_work(env, report, continuous_work, care_start - arrival, patient.care_time as f32);
+
+        finish_visit(env, &mut eval.penalty, report, nurse_idx, patient_id, patient, is_partner);
+        prev_stop_id = patient_id;
+    }
 
-                // println!("{:?}",nurse_time + patient.care_time as f32);
-                // println!("{:?}",patient.end_time);
-                // println!("{:?}",(nurse_time + patient.care_time as f32) > patient.end_time as f32);
-                // println!();
+    if lunch_before == Some(route.len()) {
+        take_lunch(env, report, &mut nurse_time, &mut continuous_work);
+    }
 
-                // println!("{:?}",)
-                if (nurse_time + patient.care_time as f32) > patient.end_time as f32 {
-                    // the stop is invalid
-                    valid = false;
-                    penalty += penalty_add;
-                } else {
-                    nurse_time += patient.care_time as f32;
-                }
+    // drive back home
+    let travel_to_time = travel_with_rest(env, &prev_stop_id, &depot_id, 0.0, &mut nurse_time, &mut continuous_work);
+    report.travel_time += travel_to_time;
+    nurse_time += travel_to_time;
+    check_continuous_work(env, report, continuous_work + travel_to_time, 0.0, 0.0);
+    report.return_time = nurse_time;
+
+    // validate the max time and max strain is not exceeded
+    if nurse_time > nurse.return_time as f32 {
+        // the nurse route is invalid
+        let overtime = nurse_time - nurse.return_time as f32;
+        report.violations.push(Violation::Overtime { minutes: overtime });
+        if cost_model.soft_time_windows {
+            eval.violation_cost += cost_model.overtime_cost.cost(overtime);
+        } else {
+            eval.penalty += penalties.return_time.cost(overtime);
+        }
+    }
+
+    if report.load > nurse.capacity {
+        // the nurse rute is invalid
+        let excess = report.load - nurse.capacity;
+        report.violations.push(Violation::Capacity { excess });
+        if cost_model.soft_time_windows {
+            eval.violation_cost += cost_model.excess_demand_cost.cost(excess as f32);
+        } else {
+            eval.penalty += penalties.capacity.cost(excess as f32);
+        }
+    }
+
+    let break_violations = report
+        .violations
+        .iter()
+        .filter(|v| matches!(v, Violation::LunchBreak { .. } | Violation::ContinuousWork { .. }))
+        .count();
+    eval.penalty += penalties.other.cost(1.0) * break_violations as f32;
+
+    return eval;
+}
+
+/// Add the load of the visit and check the nurse is qualified. The demand of a patient that needs
+/// two nurses is carried by the Patient stop only, [evaluate_stops] makes that the first route that
+/// lists the patient. The verifier only sees patient ids and uses the same rule.
+fn finish_visit(
+    env: &EnvPruned,
+    penalty: &mut f32,
+    report: &mut RouteReport,
+    nurse_idx: usize,
+    patient_id: i32,
+    patient: &PatientPruned,
+    is_partner: bool,
+) {
+    // validate the care strain
+    if !is_partner {
+        report.load += patient.demand;
+    }
 
+    // the nurse has to be qualified for the care
+    if !env.nurse_can_serve(nurse_idx, patient_id) {
+        report.violations.push(Violation::MissingSkills { patient_id });
+        *penalty += env.cost_model.penalty.skills.cost(1.0);
+    }
+}
 
+/// The continuous work after a wait and a care, a long enough wait for the window to open counts as
+/// a rest. Work past the limit is a violation, it only happens when a single trip and care is longer
+/// than the limit.
+fn check_continuous_work(env: &EnvPruned, report: &mut RouteReport, continuous_work: f32, waited: f32, care_time: f32) -> f32 {
+    let max_work = match env.break_rules.max_continuous_work {
+        Some(max_work) => max_work as f32,
+        None => return continuous_work + care_time,
+    };
+    let mut work = continuous_work;
+    if waited >= env.break_rules.rest_duration as f32 {
+        work = 0.0;
+    }
+    work += care_time;
+    if work > max_work {
+        report.violations.push(Violation::ContinuousWork { minutes: work - max_work });
+    }
+    return work;
+}
 
-                // validate the care strain
-                nurse_strain += patient.demand;
+/// Take the lunch break now, or as soon as the lunch window opens
+fn take_lunch(env: &EnvPruned, report: &mut RouteReport, nurse_time: &mut f32, continuous_work: &mut f32) {
+    let lunch = env.break_rules.lunch_break.as_ref().unwrap();
+    let break_start = nurse_time.max(lunch.start_time as f32);
+    if break_start > lunch.latest_start() {
+        // too late to fit the break in the window
+        report.violations.push(Violation::LunchBreak { late_by: break_start - lunch.latest_start() });
+    }
+    *nurse_time = break_start + lunch.duration as f32;
+    *continuous_work = 0.0;
+}
 
-                prev_stop_id = patient_id.clone();
+/// Drive route k and place the lunch break where it costs the least. Nurses that are home before
+/// the break has to start, or leave after, do not take it.
+fn evaluate_route(env: &EnvPruned, nurse_idx: usize, route: &[NurseStop], sync_starts: &HashMap<i32, f32>) -> RouteEvaluation {
+    let without_lunch = drive_route(env, nurse_idx, route, sync_starts, None);
+    let lunch = match &env.break_rules.lunch_break {
+        Some(lunch) => lunch,
+        None => return without_lunch,
+    };
+
+    let nurse: &NursePruned = env.nurses.get(nurse_idx).unwrap_or(env.nurses.last().unwrap());
+    let on_duty_at_lunch =
+        (nurse.start_time as f32) <= lunch.latest_start() && without_lunch.report.return_time > lunch.latest_start();
+    if route.is_empty() || !on_duty_at_lunch {
+        return without_lunch;
+    }
+
+    // try the break before every trip, the fewest penalties first and then the lowest cost
+    let mut best: Option<RouteEvaluation> = None;
+    for pos in 0..=route.len() {
+        let eval = drive_route(env, nurse_idx, route, sync_starts, Some(pos));
+        let better = match &best {
+            None => true,
+            Some(b) => {
+                (eval.penalty, eval.report.travel_time + eval.violation_cost)
+                    < (b.penalty, b.report.travel_time + b.violation_cost)
             }
-            NurseStop::Depot => {
-                // find the travel time
-                let travel_to_time = env.get_travel_time_between(&prev_stop_id, &0);
-
-                total_travel_time += travel_to_time;
-                nurse_time += travel_to_time;
-
-                // validate the max time and max strain is not exceeded
-                if nurse_time > env.depo_ret_time as f32 {
-                    // the nurse route is invalid
-                    valid = false;
-                    penalty += penalty_add;
+        };
+        if better {
+            best = Some(eval);
+        }
+    }
+    return best.unwrap();
+}
+
+/// The route with the first visit to every patient that needs two nurses as the Patient stop and
+/// any later visit as the partner. Which stop a genome calls the partner is not visible in the
+/// delivery string, so the demand is put on the first route that lists the patient.
+fn demand_on_first_visit(env: &EnvPruned, route: &[NurseStop], carried: &mut HashSet<i32>) -> Vec<NurseStop> {
+    return route
+        .iter()
+        .map(|stop| match stop.patient_id() {
+            Some(id) if env.patients.get((id - 1) as usize).unwrap().synchronized => {
+                if carried.insert(id) {
+                    NurseStop::Patient(id)
+                } else {
+                    NurseStop::SyncPartner(id)
                 }
+            }
+            _ => *stop,
+        })
+        .collect();
+}
 
-                if nurse_strain > nurse_capacity {
-                    // the nurse rute is invalid
-                    valid = false;
-                    penalty += penalty_add;
+/// Drive every route once. Visits to patients that need two nurses do not start before the time in
+/// sync_starts, the nurse waits there if it arrives earlier. Also returns the synchronized visits.
+fn evaluate_stops(
+    env: &EnvPruned,
+    stops: &Vec<NurseStop>,
+    sync_starts: &HashMap<i32, f32>,
+    mut cache: Option<&mut RouteCache>,
+) -> (EvaluationReport, Vec<(i32, usize, f32)>) {
+    let mut report = EvaluationReport {
+        routes: Vec::with_capacity(env.nurses.len()),
+        dropped_patients: Vec::new(),
+        dropped_cost: 0.0,
+        violations: Vec::new(),
+        cost: 0.0,
+        penalty: 0.0,
+        penalty_model: env.cost_model.penalty.clone(),
+    };
+    let mut sync_visits = Vec::new();
+
+    // with optional patients the segment after the last nurse holds the patients nobody visits
+    let pool_idx = if env.allows_dropped_patients() { Some(env.nurses.len()) } else { None };
+
+    // the synchronized patients whose demand an earlier route carries
+    let has_synchronized_patients = env.has_synchronized_patients();
+    let mut carried: HashSet<i32> = HashSet::new();
+
+    // route k is driven by nurse k
+    let mut last_route = 0;
+    for (nurse_idx, route) in stops.split(|s| *s == NurseStop::Depot).enumerate() {
+        last_route = nurse_idx;
+
+        if Some(nurse_idx) == pool_idx {
+            for stop in route {
+                let patient_id = match stop {
+                    NurseStop::Patient(patient_id) => *patient_id,
+                    _ => continue,
+                };
+                report.dropped_patients.push(patient_id);
+                match env.patients.get((patient_id - 1) as usize).unwrap().drop_penalty {
+                    Some(drop_penalty) => report.dropped_cost += drop_penalty,
+                    None => {
+                        // mandatory patients can not be dropped
+                        report.violations.push(Violation::MandatoryDropped { patient_id });
+                        report.penalty += env.cost_model.penalty.other.cost(1.0);
+                    }
                 }
+            }
+            report.cost += report.dropped_cost;
+            continue;
+        }
+
+        let relabeled;
+        let route = if has_synchronized_patients {
+            relabeled = demand_on_first_visit(env, route, &mut carried);
+            &relabeled[..]
+        } else {
+            route
+        };
 
-                // reset the nurse values
-                nurse_time = 0.0;
-                prev_stop_id = 0;
-                nurse_strain = 0;
+        // a route waiting for a second nurse depends on the other routes and is not cached
+        let waits_for_partner = route
+            .iter()
+            .any(|stop| stop.patient_id().map_or(false, |id| sync_starts.contains_key(&id)));
+        let route_eval = match cache.as_deref_mut() {
+            Some(cache) if !waits_for_partner => {
+                cache.get_or_evaluate(nurse_idx, route, || evaluate_route(env, nurse_idx, route, sync_starts))
             }
+            _ => Rc::new(evaluate_route(env, nurse_idx, route, sync_starts)),
+        };
+        report.cost += route_eval.report.travel_time + route_eval.violation_cost;
+        report.penalty += route_eval.penalty;
+        sync_visits.extend_from_slice(&route_eval.sync_visits);
+        report.routes.push(Rc::clone(&route_eval.report));
+    }
+
+    if last_route >= env.nurses.len() && Some(last_route) != pool_idx {
+        // more routes than there are nurses
+        report.violations.push(Violation::TooManyRoutes { routes: last_route + 1, nurses: env.nurses.len() });
+        report.penalty += env.cost_model.penalty.other.cost(1.0);
+    }
+
+    return (report, sync_visits);
+}
+
+/// The visits to every patient that needs two nurses, as (route, care start)
+fn group_sync_visits(sync_visits: &Vec<(i32, usize, f32)>) -> HashMap<i32, Vec<(usize, f32)>> {
+    let mut grouped: HashMap<i32, Vec<(usize, f32)>> = HashMap::new();
+    for (patient_id, nurse_idx, care_start) in sync_visits {
+        grouped.entry(*patient_id).or_insert_with(Vec::new).push((*nurse_idx, *care_start));
+    }
+    return grouped;
+}
+
+/// The patients that need two nurses but are not visited by two different nurses starting at the
+/// same time. Patients where both visits are dropped are not counted.
+fn sync_violations(sync_visits: &Vec<(i32, usize, f32)>) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (patient_id, visits) in group_sync_visits(sync_visits) {
+        if visits.len() != 2 || visits[0].0 == visits[1].0 {
+            violations.push(Violation::Synchronization { patient_id, gap: None });
+            continue;
+        }
+        let gap = (visits[0].1 - visits[1].1).abs();
+        if gap >= 1e-3 {
+            violations.push(Violation::Synchronization { patient_id, gap: Some(gap) });
         }
     }
-    let travel_to_time = env.get_travel_time_between(&prev_stop_id, &0);
-    total_travel_time += travel_to_time;
-    nurse_time += travel_to_time;
-    if nurse_time > env.depo_ret_time as f32 {
-        // the nurse route is invalid
-        valid = false;
-        penalty += penalty_add;
+    violations.sort_by_key(|v| match v {
+        Violation::Synchronization { patient_id, .. } => *patient_id,
+        _ => 0,
+    });
+    return violations;
+}
+
+/// Evaluate the genome route by route
+pub fn evaluate(env: &EnvPruned, genotype: &Genotype) -> EvaluationReport {
+    return evaluate_with_cache(env, genotype, None);
+}
+
+/// Evaluate the genome, routes found in the cache are not driven again
+pub fn evaluate_with_cache(env: &EnvPruned, genotype: &Genotype, mut cache: Option<&mut RouteCache>) -> EvaluationReport {
+    if let Some(cache) = cache.as_deref_mut() {
+        cache.check_env(env);
+    }
+    let mut sync_starts: HashMap<i32, f32> = HashMap::new();
+    let (mut report, mut sync_visits) = evaluate_stops(env, &genotype.stops, &sync_starts, cache.as_deref_mut());
+
+    if env.has_synchronized_patients() {
+        // let the first nurse to arrive wait for the other one. Waiting can push later visits on the
+        // route so repeat until the start times settle, when the routes wait for each other in a
+        // cycle they never do and the visits are counted as violations
+        let max_passes = env.patients.iter().filter(|p| p.synchronized).count() + 1;
+        for _ in 0..max_passes {
+            let mut changed = false;
+            for (patient_id, visits) in group_sync_visits(&sync_visits) {
+                let latest = visits.iter().map(|(_, care_start)| *care_start).fold(f32::MIN, f32::max);
+                let earliest = visits.iter().map(|(_, care_start)| *care_start).fold(f32::MAX, f32::min);
+                if latest - earliest >= 1e-3 {
+                    sync_starts.insert(patient_id, latest);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            (report, sync_visits) = evaluate_stops(env, &genotype.stops, &sync_starts, cache.as_deref_mut());
+        }
+
+        let mut violations = sync_violations(&sync_visits);
+        report.penalty += env.cost_model.penalty.other.cost(1.0) * violations.len() as f32;
+        report.violations.append(&mut violations);
     }
 
-    genotype.travel_time = Option::from(total_travel_time * penalty);
-    genotype.valid = Option::from(valid);
+    return report;
+}
+
+fn set_from_report(env: &EnvPruned, genotype: &mut Genotype, report: &EvaluationReport) {
+    genotype.travel_time = Option::from(report.fitness());
+    genotype.valid = Option::from(report.is_valid());
+    genotype.lateness = Option::from(report.total_lateness());
+    genotype.break_violations = Option::from(report.break_violations());
+    genotype.violation_counts = Option::from(report.violation_counts());
+    genotype.fitness = Option::from(report.comparable_fitness(env.cost_model.objective));
+}
+
+/// Evaluate the genome with the route cache of the current thread
+pub fn calculate_and_set_travel_time(env: &EnvPruned, genotype: &mut Genotype) {
+    calculate_and_get_report(env, genotype);
+}
+
+/// Evaluate the genome without the route cache, for a one off evaluation under another cost model
+pub fn calculate_and_set_travel_time_uncached(env: &EnvPruned, genotype: &mut Genotype) {
+    let report = evaluate(env, genotype);
+    set_from_report(env, genotype, &report);
+}
+
+/// Same as [calculate_and_set_travel_time] but also hands back the full report
+pub fn calculate_and_get_report(env: &EnvPruned, genotype: &mut Genotype) -> EvaluationReport {
+    let report = with_thread_cache(|cache| evaluate_with_cache(env, genotype, Option::from(cache)));
+    set_from_report(env, genotype, &report);
+    return report;
 }
 
 
+/// Evaluate the genomes that have no travel time yet, only the routes that are not in the route
+/// cache of the current thread are driven
 pub fn calculate_and_set_travel_time_multiple(env: &EnvPruned, genotypes: &mut Vec<Genotype>) {
-    for gt in genotypes {
-        if gt.travel_time.is_none() {
-            calculate_and_set_travel_time(env, gt);
+    with_thread_cache(|cache| {
+        for gt in genotypes {
+            if gt.travel_time.is_none() {
+                let report = evaluate_with_cache(env, gt, Option::from(&mut *cache));
+                set_from_report(env, gt, &report);
+            }
+        }
+    });
+}
+
+/// Put every patient on a random nurse from the depot closest to it
+fn random_multi_depot_chromosome(env: &EnvPruned, depot_nurses: &Vec<Vec<usize>>) -> Vec<NurseStop> {
+    let mut rng = rand::thread_rng();
+
+    let mut routes: Vec<Vec<NurseStop>> = vec![Vec::new(); env.nurses.len()];
+    for n in 1..(env.patients.len() + 1) {
+        let patient_id = n as i32;
+        let closest_depot = (0..env.depots.len())
+            .filter(|d| !depot_nurses[*d].is_empty())
+            .min_by(|a, b| {
+                let a_idx = env.depots[*a].travel_index as i32;
+                let b_idx = env.depots[*b].travel_index as i32;
+                env.get_travel_time_between(&a_idx, &patient_id)
+                    .total_cmp(env.get_travel_time_between(&b_idx, &patient_id))
+            })
+            .unwrap();
+        let nurse_idx = *depot_nurses[closest_depot].choose(&mut rng).unwrap();
+        routes[nurse_idx].push(NurseStop::Patient(patient_id));
+
+        if env.patients.get(n - 1).unwrap().synchronized {
+            // the second nurse comes from the same depot when possible
+            let others: Vec<usize> = depot_nurses[closest_depot].iter().cloned().filter(|k| *k != nurse_idx).collect();
+            let partner_idx = match others.choose(&mut rng) {
+                Some(k) => *k,
+                None => (nurse_idx + 1) % env.nurses.len(),
+            };
+            routes[partner_idx].push(NurseStop::SyncPartner(patient_id));
+        }
+    }
+
+    let mut chromosome: Vec<NurseStop> = Vec::new();
+    for (nurse_idx, mut route) in routes.into_iter().enumerate() {
+        if nurse_idx > 0 {
+            chromosome.push(NurseStop::Depot);
         }
+        route.shuffle(&mut rng);
+        chromosome.append(&mut route);
     }
+    return chromosome;
 }
 
 pub fn generate_random_genome(env: &EnvPruned, pop_size: i32) -> Vec<Genotype> {
@@ -290,12 +797,34 @@ pub fn generate_random_genome(env: &EnvPruned, pop_size: i32) -> Vec<Genotype> {
     let num_patients = env.patients.len();
     let num_nurses = env.number_nurses - 1; // sub 1 because the first is implisit
 
+    // the nurses starting from each depot
+    let mut depot_nurses: Vec<Vec<usize>> = vec![Vec::new(); env.depots.len()];
+    for (nurse_idx, nurse) in env.nurses.iter().enumerate() {
+        depot_nurses[nurse.depot].push(nurse_idx);
+    }
+
     let mut ret: Vec<Genotype> = Vec::new();
     for _ in 0..pop_size {
+        if env.depots.len() > 1 {
+            // route k starts at the depot of nurse k, so a full shuffle would send nurses across town
+            let mut chromosome = random_multi_depot_chromosome(env, &depot_nurses);
+            if env.allows_dropped_patients() {
+                // start with every patient planned and an empty unassigned segment
+                chromosome.push(NurseStop::Depot);
+            }
+            let mut genome = Genotype::new(chromosome, MetaGenes::new());
+            repair_skill_mismatch(&mut genome, env);
+            ret.push(genome);
+            continue;
+        }
+
         let mut chromosome: Vec<NurseStop> = Vec::new();
 
         for n in 1..(num_patients + 1) {
-            chromosome.push(NurseStop::Patient(n as i32))
+            chromosome.push(NurseStop::Patient(n as i32));
+            if env.patients.get(n - 1).unwrap().synchronized {
+                chromosome.push(NurseStop::SyncPartner(n as i32));
+            }
         }
 
         for _ in 0..num_nurses {
@@ -303,7 +832,13 @@ pub fn generate_random_genome(env: &EnvPruned, pop_size: i32) -> Vec<Genotype> {
         }
 
         chromosome.shuffle(&mut rng);
-        ret.push(Genotype::new(chromosome,MetaGenes::new()));
+        if env.allows_dropped_patients() {
+            // start with every patient planned and an empty unassigned segment
+            chromosome.push(NurseStop::Depot);
+        }
+        let mut genome = Genotype::new(chromosome, MetaGenes::new());
+        repair_skill_mismatch(&mut genome, env);
+        ret.push(genome);
     }
 
     return ret;
@@ -317,3 +852,128 @@ pub fn generate_random_genome(env: &EnvPruned, pop_size: i32) -> Vec<Genotype> {
 //
 //  Crossover
 //
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::breaks::LunchBreak;
+    use crate::mutation::drop_patient_mutate;
+    use crate::train_data_parsing::get_train_sett;
+
+    /// Train set 0 where every patient can be left out for a drop penalty of 100
+    pub(crate) fn optional_patients_env() -> EnvPruned {
+        let mut env = get_train_sett(0);
+        for patient in env.patients.iter_mut() {
+            patient.drop_penalty = Option::from(100.0);
+        }
+        return env;
+    }
+
+    /// A random genome with count of its patients moved to the pool of dropped patients
+    pub(crate) fn genome_with_dropped(env: &EnvPruned, count: usize) -> Genotype {
+        let mut genome = generate_random_genome(env, 1).pop().unwrap();
+        for _ in 0..count {
+            drop_patient_mutate(&mut genome, env);
+        }
+        return genome;
+    }
+
+    #[test]
+    fn delivery_str_leaves_out_dropped_patients() {
+        let env = optional_patients_env();
+        let genome = genome_with_dropped(&env, 5);
+
+        let dropped = genome.get_dropped_patients(&env);
+        let routes = genome.get_as_delivery_str(&env);
+        assert_eq!(dropped.len(), 5);
+        assert_eq!(routes.len(), env.nurses.len());
+        assert!(routes.iter().flatten().all(|id| !dropped.contains(id)));
+        assert_eq!(routes.iter().flatten().count() + dropped.len(), env.patients.len());
+    }
+
+    /// Train set 0 with nurse 0 working all day and the care and single window of some patients set
+    fn break_env(patients: &[(i32, i32, i32, i32)]) -> EnvPruned {
+        let mut env = get_train_sett(0);
+        env.nurses[0].start_time = 0;
+        env.nurses[0].return_time = 1000;
+        for (patient_id, start_time, end_time, care_time) in patients {
+            let patient = env.patients.get_mut((patient_id - 1) as usize).unwrap();
+            patient.start_time = *start_time;
+            patient.end_time = *end_time;
+            patient.time_windows = vec![(*start_time, *end_time)];
+            patient.care_time = *care_time;
+        }
+        return env;
+    }
+
+    fn has_lunch_violation(eval: &RouteEvaluation) -> bool {
+        return eval.report.violations.iter().any(|v| matches!(v, Violation::LunchBreak { .. }));
+    }
+
+    #[test]
+    fn lunch_is_taken_where_it_breaks_no_rule() {
+        // patient 1 has to be seen before the lunch window ends, patient 2 only opens after it
+        let mut env = break_env(&[(1, 0, 40, 10), (2, 100, 1000, 10)]);
+        env.break_rules.lunch_break = Option::from(LunchBreak { start_time: 50, end_time: 110, duration: 30 });
+        let route = [NurseStop::Patient(1), NurseStop::Patient(2)];
+        let no_sync = HashMap::new();
+
+        // before patient 1 it is late, after patient 2 the lunch window is over
+        let lunch_first = drive_route(&env, 0, &route, &no_sync, Some(0));
+        assert!(lunch_first.report.violations.iter().any(|v| matches!(v, Violation::TimeWindow { patient_id: 1, .. })));
+        let lunch_last = drive_route(&env, 0, &route, &no_sync, Some(2));
+        assert!(has_lunch_violation(&lunch_last));
+
+        // in between the break fits in the wait for patient 2 and the nurse is home as early
+        let best = evaluate_route(&env, 0, &route, &no_sync);
+        assert!(best.report.violations.is_empty(), "{:?}", best.report.violations);
+        assert_eq!(best.report.return_time, drive_route(&env, 0, &route, &no_sync, None).report.return_time);
+    }
+
+    #[test]
+    fn lunch_after_the_window_is_late_by_the_overrun() {
+        let mut env = break_env(&[(1, 0, 1000, 100)]);
+        env.break_rules.lunch_break = Option::from(LunchBreak { start_time: 0, end_time: 60, duration: 30 });
+        let route = [NurseStop::Patient(1)];
+
+        let after_care = drive_route(&env, 0, &route, &HashMap::new(), Some(1));
+        let back_from_care = env.get_travel_time_between(&0, &1) + 100.0;
+        let late_by = after_care
+            .report
+            .violations
+            .iter()
+            .find_map(|v| match v {
+                Violation::LunchBreak { late_by } => Some(*late_by),
+                _ => None,
+            })
+            .unwrap();
+        assert!((late_by - (back_from_care - 30.0)).abs() < 1e-3);
+
+        // taking it right away is allowed
+        assert!(!has_lunch_violation(&evaluate_route(&env, 0, &route, &HashMap::new())));
+    }
+
+    #[test]
+    fn care_longer_than_the_work_limit_is_a_violation() {
+        let mut env = break_env(&[(3, 0, 1000, 100)]);
+        env.break_rules.max_continuous_work = Option::from(50);
+        env.break_rules.rest_duration = 10;
+        let eval = evaluate_route(&env, 0, &[NurseStop::Patient(3)], &HashMap::new());
+
+        let trip = *env.get_travel_time_between(&0, &3);
+        let overruns: Vec<f32> = eval
+            .report
+            .violations
+            .iter()
+            .filter_map(|v| match v {
+                Violation::ContinuousWork { minutes } => Some(*minutes),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(overruns.len(), 1);
+        assert!((overruns[0] - (trip + 100.0 - 50.0)).abs() < 1e-3);
+
+        // the nurse rests before driving home
+        assert!((eval.report.return_time - (trip + 100.0 + 10.0 + trip)).abs() < 1e-3);
+    }
+}
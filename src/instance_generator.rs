@@ -0,0 +1,206 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::breaks::BreakRules;
+use crate::cost_model::CostModel;
+use crate::train_data_parsing::{uniform_fleet, DepotPruned, EnvPruned, PatientPruned};
+
+/// How the patients are spread out, named after the Solomon instance classes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpatialDistribution {
+    // C: patients grouped around a few cluster centers
+    Clustered { num_clusters: usize, spread: f32 },
+
+    // R: patients uniformly spread over the grid
+    Random,
+
+    // RC: half clustered, half random
+    Mixed { num_clusters: usize, spread: f32 },
+}
+
+impl SpatialDistribution {
+    /// The distribution of a Solomon class name: c, r or rc
+    pub fn from_class(name: &str) -> Option<SpatialDistribution> {
+        return match name.to_lowercase().as_str() {
+            "c" => Option::from(SpatialDistribution::Clustered { num_clusters: 8, spread: 6.0 }),
+            "r" => Option::from(SpatialDistribution::Random),
+            "rc" => Option::from(SpatialDistribution::Mixed { num_clusters: 8, spread: 6.0 }),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub num_patients: usize,
+    pub distribution: SpatialDistribution,
+
+    // side length of the square the patients are placed in, the depot is in the middle
+    pub grid_size: i32,
+
+    // the depot return time, every time window is inside [0, horizon]
+    pub horizon: i32,
+
+    // 0.0 gives windows exactly as long as the care time, 1.0 gives windows spanning the whole day
+    pub time_window_tightness: f32,
+
+    // inclusive ranges
+    pub demand_range: (i32, i32),
+    pub care_time_range: (i32, i32),
+
+    pub nbr_nurses: i32,
+    pub capacity_nurse: i32,
+}
+
+impl GeneratorConfig {
+    /// Read a config written as generated:<c|r|rc>:<patients>:<seed>, None if it is not one
+    pub fn parse(spec: &str) -> Option<GeneratorConfig> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() != 4 || parts[0] != "generated" {
+            return None;
+        }
+        let distribution = SpatialDistribution::from_class(parts[1])?;
+        let num_patients = parts[2].parse::<usize>().ok()?;
+        let seed = parts[3].parse::<u64>().ok()?;
+        return Option::from(GeneratorConfig::new(seed, num_patients, distribution));
+    }
+
+    /// A configuration close to the bundled train sets
+    pub fn new(seed: u64, num_patients: usize, distribution: SpatialDistribution) -> GeneratorConfig {
+        return GeneratorConfig {
+            seed,
+            num_patients,
+            distribution,
+            grid_size: 100,
+            horizon: 1000,
+            time_window_tightness: 0.2,
+            demand_range: (1, 40),
+            care_time_range: (10, 90),
+            nbr_nurses: 25,
+            capacity_nurse: 200,
+        };
+    }
+}
+
+/// Sample a point from a normal distribution around the center with the box muller transform
+fn sample_around(rng: &mut StdRng, center: (f32, f32), spread: f32) -> (f32, f32) {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen();
+    let radius = (-2.0 * u1.ln()).sqrt() * spread;
+    let angle = 2.0 * std::f32::consts::PI * u2;
+    return (center.0 + radius * angle.cos(), center.1 + radius * angle.sin());
+}
+
+fn generate_coords(config: &GeneratorConfig, rng: &mut StdRng) -> Vec<(i32, i32)> {
+    let grid = config.grid_size as f32;
+    let (num_clusters, spread, num_clustered) = match config.distribution {
+        SpatialDistribution::Clustered { num_clusters, spread } => (num_clusters, spread, config.num_patients),
+        SpatialDistribution::Random => (0, 0.0, 0),
+        SpatialDistribution::Mixed { num_clusters, spread } => (num_clusters, spread, config.num_patients / 2),
+    };
+
+    let centers: Vec<(f32, f32)> = (0..num_clusters.max(1))
+        .map(|_| (rng.gen_range(0.0..grid), rng.gen_range(0.0..grid)))
+        .collect();
+
+    let mut coords = Vec::with_capacity(config.num_patients);
+    for n in 0..config.num_patients {
+        let (x, y) = if n < num_clustered {
+            let center = centers[rng.gen_range(0..centers.len())];
+            sample_around(rng, center, spread)
+        } else {
+            (rng.gen_range(0.0..grid), rng.gen_range(0.0..grid))
+        };
+        coords.push((x.round().clamp(0.0, grid) as i32, y.round().clamp(0.0, grid) as i32));
+    }
+    return coords;
+}
+
+/// Generate a random instance, the same config (and seed) always gives the same instance.
+///
+/// Every patient can be served on its own: the window opens after a nurse can drive there from the
+/// depot and closes early enough to get back before the horizon.
+pub fn generate_instance(config: &GeneratorConfig) -> EnvPruned {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let depot = (config.grid_size / 2, config.grid_size / 2);
+    let coords = generate_coords(config, &mut rng);
+
+    let mut env = EnvPruned {
+        set_name: format!("generated_{}_{}", config.num_patients, config.seed),
+        number_nurses: config.nbr_nurses,
+        capacity_nurse: config.capacity_nurse,
+        benchmark: 0.0,
+        depo_ret_time: config.horizon,
+        depots: vec![DepotPruned {
+            travel_index: 0,
+            return_time: config.horizon,
+            x_coord: depot.0,
+            y_coord: depot.1,
+        }],
+        nurses: uniform_fleet(config.nbr_nurses, config.capacity_nurse, config.horizon),
+        patients: Vec::with_capacity(config.num_patients),
+        skill_names: Vec::new(),
+        cost_model: CostModel::default(),
+        _travel_jump_size: 0,
+        travel_matrix: Vec::new(),
+        travel_periods: Vec::new(),
+        break_rules: BreakRules::none(),
+    };
+
+    for (idx, (x, y)) in coords.iter().enumerate() {
+        let care_time = rng.gen_range(config.care_time_range.0..=config.care_time_range.1);
+        let demand = rng.gen_range(config.demand_range.0..=config.demand_range.1);
+
+        let dx = (x - depot.0) as f32;
+        let dy = (y - depot.1) as f32;
+        let depot_dist = (dx * dx + dy * dy).sqrt().ceil() as i32;
+
+        // the window has to fit between driving out from the depot and driving back
+        let earliest_start = depot_dist;
+        let latest_end = (config.horizon - depot_dist).max(earliest_start + care_time);
+        let slack = latest_end - earliest_start - care_time;
+
+        let width = care_time + (slack as f32 * config.time_window_tightness.clamp(0.0, 1.0)).round() as i32;
+        let start_time = rng.gen_range(earliest_start..=(latest_end - width));
+
+        env.patients.push(PatientPruned {
+            travel_index: idx + 1,
+            care_time,
+            demand,
+            end_time: start_time + width,
+            start_time,
+            x_coord: *x,
+            y_coord: *y,
+            required_skills: 0,
+            time_windows: vec![(start_time, start_time + width)],
+            drop_penalty: None,
+            synchronized: false,
+        });
+    }
+
+    env.rebuild_travel_matrix_from_coords();
+    return env;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_config_gives_same_instance() {
+        let distributions = [
+            SpatialDistribution::Random,
+            SpatialDistribution::Clustered { num_clusters: 4, spread: 8.0 },
+            SpatialDistribution::Mixed { num_clusters: 3, spread: 10.0 },
+        ];
+        for distribution in distributions {
+            let config = GeneratorConfig::new(42, 60, distribution);
+            assert_eq!(generate_instance(&config), generate_instance(&config));
+
+            let other_seed = GeneratorConfig::new(43, 60, distribution);
+            assert_ne!(generate_instance(&config), generate_instance(&other_seed));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::train_data_parsing::{EnvPruned, TravelPeriodPruned};
+
+/// Which patients to keep when cutting down an instance
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatientSelection {
+    // the patients with id 1..=n
+    FirstN(usize),
+
+    // k patients picked at random, the same seed always picks the same patients
+    RandomK { k: usize, seed: u64 },
+
+    // the patients where every window is inside [start_time, end_time]
+    TimeBand { start_time: i32, end_time: i32 },
+
+    // the listed patient ids
+    Ids(Vec<i32>),
+}
+
+impl PatientSelection {
+    /// Read a selection from the command line: first:<n>, random:<k>:<seed>, band:<start>:<end> or
+    /// ids:<id>,<id>,... None if it is not one
+    pub fn parse(spec: &str) -> Option<PatientSelection> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        return match parts.as_slice() {
+            ["first", n] => Option::from(PatientSelection::FirstN(n.parse().ok()?)),
+            ["random", k, seed] => Option::from(PatientSelection::RandomK { k: k.parse().ok()?, seed: seed.parse().ok()? }),
+            ["band", start_time, end_time] => Option::from(PatientSelection::TimeBand {
+                start_time: start_time.parse().ok()?,
+                end_time: end_time.parse().ok()?,
+            }),
+            ["ids", ids] => {
+                let ids: Result<Vec<i32>, _> = ids.split(',').map(|id| id.trim().parse::<i32>()).collect();
+                Option::from(PatientSelection::Ids(ids.ok()?))
+            }
+            _ => None,
+        };
+    }
+}
+
+/// Maps the patient ids of a subset instance back to the instance it was cut from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubsetMapping {
+    // the original id of subset patient k + 1
+    pub original_ids: Vec<i32>,
+}
+
+impl SubsetMapping {
+    pub fn to_original(&self, patient_id: i32) -> i32 {
+        return *self.original_ids.get((patient_id - 1) as usize).unwrap();
+    }
+
+    /// The id the patient has in the subset, None if it was not kept
+    pub fn from_original(&self, original_id: i32) -> Option<i32> {
+        return self
+            .original_ids
+            .iter()
+            .position(|id| *id == original_id)
+            .map(|idx| (idx + 1) as i32);
+    }
+
+    /// Translate the routes of a subset solution, ex from [Genotype::get_as_delivery_str], to the
+    /// original patient ids
+    pub fn routes_to_original(&self, routes: &Vec<Vec<i32>>) -> Vec<Vec<i32>> {
+        return routes
+            .iter()
+            .map(|route| route.iter().map(|id| self.to_original(*id)).collect())
+            .collect();
+    }
+}
+
+fn selected_ids(env: &EnvPruned, selection: &PatientSelection) -> Vec<i32> {
+    let all_ids = 1..=(env.patients.len() as i32);
+    return match selection {
+        PatientSelection::FirstN(n) => all_ids.take(*n).collect(),
+        PatientSelection::RandomK { k, seed } => {
+            let mut rng = StdRng::seed_from_u64(*seed);
+            let mut ids: Vec<i32> = all_ids.collect::<Vec<i32>>().choose_multiple(&mut rng, *k).cloned().collect();
+            ids.sort();
+            ids
+        }
+        PatientSelection::TimeBand { start_time, end_time } => all_ids
+            .filter(|id| {
+                let patient = env.patients.get((id - 1) as usize).unwrap();
+                patient.start_time >= *start_time && patient.end_time <= *end_time
+            })
+            .collect(),
+        PatientSelection::Ids(ids) => {
+            let mut kept: Vec<i32> = Vec::with_capacity(ids.len());
+            for id in ids {
+                if *id >= 1 && *id as usize <= env.patients.len() && !kept.contains(id) {
+                    kept.push(*id);
+                }
+            }
+            kept
+        }
+    };
+}
+
+/// A name part that tells selections apart, ex random_10_seed_1
+fn selection_name(selection: &PatientSelection) -> String {
+    return match selection {
+        PatientSelection::FirstN(n) => format!("first_{}", n),
+        PatientSelection::RandomK { k, seed } => format!("random_{}_seed_{}", k, seed),
+        PatientSelection::TimeBand { start_time, end_time } => format!("band_{}_{}", start_time, end_time),
+        PatientSelection::Ids(ids) => format!("ids_{}", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join("-")),
+    };
+}
+
+/// Pick the rows and columns of the kept stops out of a flat travel matrix
+fn reduce_matrix(matrix: &Vec<f32>, jump_size: usize, kept_stops: &Vec<usize>) -> Vec<f32> {
+    let mut reduced = Vec::with_capacity(kept_stops.len() * kept_stops.len());
+    for from in kept_stops {
+        for to in kept_stops {
+            reduced.push(*matrix.get(from * jump_size + to).unwrap());
+        }
+    }
+    return reduced;
+}
+
+/// Cut an instance down to the selected patients. The patients are renumbered 1..=k in the order
+/// they are selected, depots, nurses and the rules are kept as they are. Unknown ids in a selection
+/// are skipped. The set name is the original name followed by the selection.
+pub fn subset_instance(env: &EnvPruned, selection: &PatientSelection) -> (EnvPruned, SubsetMapping) {
+    let original_ids = selected_ids(env, selection);
+    let num_patients = original_ids.len();
+
+    // the old travel index of every stop in the new order: main depot, patients, extra depots
+    let mut kept_stops: Vec<usize> = Vec::with_capacity(1 + num_patients + env.depots.len() - 1);
+    kept_stops.push(env.depots[0].travel_index);
+    for id in &original_ids {
+        kept_stops.push(env.patients.get((id - 1) as usize).unwrap().travel_index);
+    }
+
+    let mut depots = env.depots.clone();
+    for (idx, depot) in depots.iter_mut().enumerate().skip(1) {
+        kept_stops.push(depot.travel_index);
+        depot.travel_index = num_patients + idx;
+    }
+
+    let patients = original_ids
+        .iter()
+        .enumerate()
+        .map(|(idx, id)| {
+            let mut patient = env.patients.get((id - 1) as usize).unwrap().clone();
+            patient.travel_index = idx + 1;
+            patient
+        })
+        .collect();
+
+    let jump_size = env._travel_jump_size as usize;
+    let subset = EnvPruned {
+        set_name: format!("{}_subset_{}", env.set_name, selection_name(selection)),
+        number_nurses: env.number_nurses,
+        capacity_nurse: env.capacity_nurse,
+        // the benchmark is for the full instance
+        benchmark: 0.0,
+        depo_ret_time: env.depo_ret_time,
+        depots,
+        nurses: env.nurses.clone(),
+        patients,
+        skill_names: env.skill_names.clone(),
+        cost_model: env.cost_model.clone(),
+        _travel_jump_size: kept_stops.len() as i32,
+        travel_matrix: reduce_matrix(&env.travel_matrix, jump_size, &kept_stops),
+        travel_periods: env
+            .travel_periods
+            .iter()
+            .map(|period| TravelPeriodPruned {
+                start_time: period.start_time,
+                travel_matrix: reduce_matrix(&period.travel_matrix, jump_size, &kept_stops),
+            })
+            .collect(),
+        break_rules: env.break_rules.clone(),
+    };
+
+    return (subset, SubsetMapping { original_ids });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train_data_parsing::get_train_sett;
+
+    #[test]
+    fn subsets_of_the_same_size_get_different_names() {
+        let env = get_train_sett(0);
+        let (first, _) = subset_instance(&env, &PatientSelection::RandomK { k: 10, seed: 1 });
+        let (second, _) = subset_instance(&env, &PatientSelection::RandomK { k: 10, seed: 2 });
+        let (head, _) = subset_instance(&env, &PatientSelection::FirstN(10));
+        assert_ne!(first.set_name, second.set_name);
+        assert_ne!(first.set_name, head.set_name);
+    }
+
+    #[test]
+    fn selections_parse_from_the_command_line() {
+        assert_eq!(PatientSelection::parse("first:20"), Some(PatientSelection::FirstN(20)));
+        assert_eq!(PatientSelection::parse("random:10:3"), Some(PatientSelection::RandomK { k: 10, seed: 3 }));
+        assert_eq!(
+            PatientSelection::parse("band:0:300"),
+            Some(PatientSelection::TimeBand { start_time: 0, end_time: 300 })
+        );
+        assert_eq!(PatientSelection::parse("ids:4,2,9"), Some(PatientSelection::Ids(vec![4, 2, 9])));
+        assert_eq!(PatientSelection::parse("random:10"), None);
+        assert_eq!(PatientSelection::parse("ids:4,x"), None);
+    }
+}
@@ -14,16 +14,34 @@ use itertools::{iterate, Itertools};
 use rand::Rng;
 use crate::crossover::{edge_crossover, partially_mapped_crossover, simple_sub_path_crossover};
 
-use crate::genalg::{calculate_and_set_travel_time, calculate_and_set_travel_time_multiple, calculate_pop_diversity, generate_random_genome, Genotype};
-use crate::mutation::{brute_f_seg, mutate};
+use crate::adaptive_penalty::{AdaptivePenalties, AdaptivePenaltyConfig};
+use crate::cost_model::{CostModel, ObjectiveMode};
+use crate::fitness::Fitness;
+use crate::nsga2::{run_nsga2, Nsga2Config};
+use crate::genalg::{calculate_and_set_travel_time, calculate_and_set_travel_time_multiple, calculate_and_set_travel_time_uncached, calculate_pop_diversity, generate_random_genome, Genotype};
+use crate::mutation::{brute_f_seg, mutate, repair_skill_mismatch};
 use crate::selection::{elitism_parent_selection, elitism_survivor_selection, random_best_half_parent_selection, rank_parent_selection, tournament_parent_selection, tournament_pick, tournament_surivor_selection};
-use crate::train_data_parsing::{EnvPruned, get_train_sett};
+use crate::feasibility::analyse_feasibility;
+use crate::instance_subset::{subset_instance, PatientSelection};
+use crate::train_data_parsing::{EnvPruned, InstanceSource};
 
 mod genalg;
 mod selection;
 mod train_data_parsing;
 mod crossover;
 mod mutation;
+mod feasibility;
+mod instance_generator;
+mod cost_model;
+mod breaks;
+mod instance_subset;
+mod evaluation;
+mod adaptive_penalty;
+mod move_evaluation;
+mod route_cache;
+mod fitness;
+mod nsga2;
+mod verifier;
 
 /*
 
@@ -47,7 +65,7 @@ crossover som kopierer en bit av en path fra en node til samme node i parent 2
 
 #[derive(Clone,Debug)]
 pub struct GenAlgConfig {
-    train_set: i32,
+    instance: InstanceSource,
     pop_size: usize,
 
     children_per_parent_pair: usize,
@@ -65,6 +83,12 @@ pub struct GenAlgConfig {
 
     cross_per: i32,
     cross_num: i32,
+
+    cost_model: CostModel,
+
+    // adjust the penalty weights to the feasibility of the offspring, None keeps them fixed. With
+    // soft time windows only the skill and other penalties are adjusted
+    adaptive_penalty: Option<AdaptivePenaltyConfig>,
 }
 
 pub struct NewBestMsg {
@@ -111,6 +135,8 @@ fn gen_child(
             false => Genotype::new(parent_2.stops.clone(), parent_2.meta_genes.clone()),
         }
     };
+    // crossover can drop patients on routes whose nurse is not qualified for them
+    repair_skill_mismatch(&mut child, environment);
     if (child.meta_genes.mut_rate + mut_1_delta) > rng.gen::<f32>() {
         mutate(&mut child, environment, itr);
         while (config.next_mut_chance + mut_2_delta) > rng.gen::<f32>() {
@@ -161,6 +187,7 @@ struct RoundRobinComs {
 fn start_worker(
     best_sender: &Sender<Option<NewBestMsg>>,
     config: &GenAlgConfig,
+    environment: &EnvPruned,
     round_send: Sender<Vec<Genotype>>,
     round_receive: Receiver<Vec<Genotype>>,
     tr_num: i32,
@@ -168,14 +195,16 @@ fn start_worker(
 ) -> JoinHandle<()> {
     let s_clone = best_sender.clone();
     let cfg_clone = config.clone();
+    let env_clone = environment.clone();
     let handle = thread::spawn(move || {
-        gen_alg_worker(cfg_clone, s_clone, tr_num, round_send, round_receive, spike)
+        gen_alg_worker(cfg_clone, env_clone, s_clone, tr_num, round_send, round_receive, spike)
     });
     return handle;
 }
 
 pub fn gen_alg_worker(
     mut config: GenAlgConfig,
+    mut environment: EnvPruned,
     send_channel: Sender<Option<NewBestMsg>>,
     tr_num: i32,
     cross_b_sender: Sender<Vec<Genotype>>,
@@ -185,7 +214,7 @@ pub fn gen_alg_worker(
     let mut population: Vec<Genotype> = Vec::with_capacity(config.pop_size);
     let mut children: Vec<Genotype> = Vec::new();
 
-    let mut best: f32 = 0.0;
+    let mut best: Fitness;
     let mut round_since_improve = 0;
 
     let mut mutation_rate_delta = 0.0;
@@ -208,8 +237,12 @@ pub fn gen_alg_worker(
 
     // -- initialization -- //
 
-    // generate env
-    let environment = get_train_sett(config.train_set);
+    // the instance is loaded once and shared, only the cost model comes from the config
+    environment.cost_model = config.cost_model.clone();
+    let mut adaptive_penalties = config
+        .adaptive_penalty
+        .clone()
+        .map(|adaptive_config| AdaptivePenalties::new(adaptive_config, &environment.cost_model));
 
     population.append(&mut generate_random_genome(
         &environment,
@@ -218,7 +251,7 @@ pub fn gen_alg_worker(
 
 
     calculate_and_set_travel_time_multiple(&environment, &mut population);
-    best = population.get(0).unwrap().travel_time.unwrap();
+    best = population.get(0).unwrap().fitness.unwrap();
 
     let mut round_r_waiting = false;
 
@@ -268,6 +301,12 @@ pub fn gen_alg_worker(
             let res = cross_b_reciver.try_recv();
             match res {
                 Ok(mut cross_vals) => {
+                    if adaptive_penalties.is_some() {
+                        // the other workers adapt their own penalty weights
+                        for genome in cross_vals.iter_mut() {
+                            calculate_and_set_travel_time(&environment, genome);
+                        }
+                    }
                     // let cv_l = cross_vals.len();
                     population.append(&mut cross_vals);
                     round_r_waiting = false;
@@ -279,7 +318,7 @@ pub fn gen_alg_worker(
 
         population.sort();
 
-        let pop_best = population.get(0).unwrap().travel_time.unwrap();
+        let pop_best = population.get(0).unwrap().fitness.unwrap();
         if pop_best < best {
             let used_pop = if crowd_fill > 0 {
                 // population.to_vec()
@@ -293,10 +332,19 @@ pub fn gen_alg_worker(
             let a: Vec<_> = used_pop.iter().map(|v| v.travel_time.unwrap()).collect();
             let pop_sdiv = std_deviation(&a).unwrap();
             let pop_entropy = calculate_pop_diversity(&used_pop, &environment);
+
+            let mut best_genome = used_pop.get(0).unwrap().clone();
+            if adaptive_penalties.is_some() {
+                // every worker adapts its own weights, the main thread compares genomes scored with
+                // the weights the run started with
+                let adapted = std::mem::replace(&mut environment.cost_model, config.cost_model.clone());
+                calculate_and_set_travel_time_uncached(&environment, &mut best_genome);
+                environment.cost_model = adapted;
+            }
             let msg = NewBestMsg {
                 best_cnfg: config.clone(),
                 s_div: pop_sdiv,
-                best_genome: used_pop.get(0).unwrap().clone(),
+                best_genome,
                 itr: iteration.clone() as i32,
                 thread_nmr: tr_num.clone(),
                 pop_entropy,
@@ -347,6 +395,9 @@ pub fn gen_alg_worker(
                         mutation_rate_secondary_delta,
                         iteration as i32
                     );
+                    if let Some(adaptive) = adaptive_penalties.as_mut() {
+                        adaptive.record(&child);
+                    }
                     competition_pop.push(child)
                 }
                 competition_pop.push(parent_1.clone());
@@ -396,6 +447,9 @@ pub fn gen_alg_worker(
                         mutation_rate_secondary_delta,
                         iteration as i32
                     );
+                    if let Some(adaptive) = adaptive_penalties.as_mut() {
+                        adaptive.record(&child);
+                    }
                     children.push(child)
                 }
             }
@@ -425,6 +479,16 @@ pub fn gen_alg_worker(
             }
         }
 
+        if let Some(cost_model) = adaptive_penalties.as_mut().and_then(|a| a.update(iteration)) {
+            // the old fitness values are not comparable with the new weights
+            environment.cost_model = cost_model;
+            for genome in population.iter_mut() {
+                calculate_and_set_travel_time(&environment, genome);
+            }
+            population.sort();
+            best = population.get(0).unwrap().fitness.unwrap();
+        }
+
         // println!("A {:?}", population.len());
         // println!("A {:?}", children.len());
         // -- survivor selection -- //
@@ -439,7 +503,7 @@ pub fn gen_alg_worker(
             break;
         }
     }
-    println!("thread complete");
+    println!("thread complete, route cache: {}", route_cache::thread_cache_stats());
     // println!("current best travel timme is {:?}", population.get(0).unwrap().travel_time.unwrap());
     // println!("is best genome valid: {:?} ", population.get(0).unwrap().valid.unwrap());
     println!(
@@ -459,7 +523,13 @@ pub fn gen_alg_worker(
     // calculate_pop_diversity(&population,&environment)
 }
 
-fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
+fn run_genalg(
+    spike: Option<Vec<Genotype>>,
+    instance: InstanceSource,
+    environment: &EnvPruned,
+    cost_model: CostModel,
+    adaptive_penalty: Option<AdaptivePenaltyConfig>,
+) -> Vec<Genotype> {
     /*
     0 - OK 828
     1 - OK 591
@@ -473,7 +543,7 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
     9 - N 880
      */
     let cnfg = GenAlgConfig {
-        train_set: 9,
+        instance,
         pop_size: 300,
         children_per_parent_pair: 30,
         num_parent_pairs: 30,
@@ -486,6 +556,9 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
 
         cross_per: 500,//500,//200
         cross_num: 10,//100
+
+        cost_model,
+        adaptive_penalty,
     };
     let (best_sender, best_receiver) = mpsc::channel::<Option<NewBestMsg>>();
 
@@ -535,7 +608,7 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
 
         let mut rng = rand::thread_rng();
         let tr_cfg = GenAlgConfig {
-            train_set: cnfg.train_set,
+            instance: cnfg.instance.clone(),
             pop_size: rng.gen_range(50..500),
             children_per_parent_pair: rng.gen_range(1..100),
             num_parent_pairs: rng.gen_range(2..100),
@@ -548,9 +621,12 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
 
             cross_per: rng.gen_range(10..1000),
             cross_num: rng.gen_range(10..100),
+
+            cost_model: cnfg.cost_model.clone(),
+            adaptive_penalty: cnfg.adaptive_penalty.clone(),
         };
 
-        let h = start_worker(&best_sender.to_owned(), &cnfg, s, r, tr_coms.idx, spk);
+        let h = start_worker(&best_sender.to_owned(), &cnfg, environment, s, r, tr_coms.idx, spk);
         handles.push(h);
     }
 
@@ -575,9 +651,9 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
                             let r = msg.best_genome;
 
                             let best = best_genome.get_or_insert(r.clone());
-                            if r.travel_time < best.travel_time {
+                            if r.fitness < best.fitness {
                                 best_cnfg.insert( msg.best_cnfg);
-                                println!("new best travel time {:>8.3}, valid {:>6}, thread: {:>3}, local itr: {:>6}, tr std: {:<10.3}, entropy: {:.4} ", r.travel_time.unwrap(), r.valid.unwrap(), msg.thread_nmr, msg.itr, msg.s_div, msg.pop_entropy);
+                                println!("new best travel time {:>8.3}, valid {:>6}, lateness {:>8.2}, thread: {:>3}, local itr: {:>6}, tr std: {:<10.3}, entropy: {:.4} ", r.travel_time.unwrap(), r.valid.unwrap(), r.lateness.unwrap(), msg.thread_nmr, msg.itr, msg.s_div, msg.pop_entropy);
                                 best_hist.push(r.clone());
                                 best_genome.insert(r);
                             }
@@ -605,15 +681,173 @@ fn run_genalg(spike: Option<Vec<Genotype>>) -> Vec<Genotype> {
     return best_h;
 }
 
+/// Run NSGA-II on the instance and print the trade-offs it found
+fn run_multi_objective(environment: &EnvPruned) {
+    let front = run_nsga2(environment, &Nsga2Config::default());
+
+    println!("PARETO FRONT ({} solutions):", front.len());
+    for individual in &front {
+        println!("{}", individual.objectives);
+        if environment.depots.len() > 1 {
+            println!("    {:?}", individual.genome.get_as_depot_delivery_str(environment));
+        } else {
+            println!("    {:?}", individual.genome.get_as_delivery_str(environment));
+        }
+    }
+}
+
+/// Load the instance, or print why it could not be loaded and exit with 1
+fn load_or_exit(instance: &InstanceSource) -> EnvPruned {
+    return match instance.load() {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("could not load {:?}: {}", instance, e);
+            std::process::exit(1);
+        }
+    };
+}
+
+/// Check every solution in the routes file against the instance, exits with 1 if one fails
+fn run_verify(instance_spec: &str, routes_path: &str, benchmark: f32) {
+    let environment = load_or_exit(&InstanceSource::parse(instance_spec, benchmark));
+    let solutions = match std::fs::read_to_string(routes_path).map(|text| verifier::parse_route_lines(&text)) {
+        Ok(Ok(solutions)) => solutions,
+        Ok(Err(e)) => {
+            eprintln!("could not read the routes in {}: {}", routes_path, e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("could not read {}: {}", routes_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+    for (idx, routes) in solutions.iter().enumerate() {
+        let report = verifier::verify_routes(&environment, routes);
+        if !report.passed() {
+            failed += 1;
+        }
+        print!("solution {}: {}", idx + 1, report);
+    }
+    println!("{} of {} solutions passed", solutions.len() - failed, solutions.len());
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// The value after a flag, ex 3 for --instance 3
+fn arg_value(args: &Vec<String>, flag: &str) -> Option<String> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    return args.get(idx + 1).cloned();
+}
+
+/// Usage:
+///   --instance <train set number | file.json | solomon file | - for json on stdin
+///               | generated:<c|r|rc>:<patients>:<seed>>, default 9
+///   --benchmark <best known travel time>, for solomon files
+///   --cost-model <hard | soft>[,additive][,fewest-nurses] how violations are charged and what is
+///                minimized, default hard with multiplicative penalties and the travel time
+///   --adaptive-penalties raises and lowers the penalty weights during the run to keep a share of
+///                        the offspring feasible
+///   --nsga2 runs the multi objective search on the instance instead
+///   --verify <instance> <routes file> checks every [[...]] line of the file instead
+///   --subset <first:n | random:k:seed | band:start:end | ids:a,b,c> runs on some of the patients,
+///            the routes are also printed with the original ids
+///   --save <file.json> writes the instance in the train set format instead of running
 fn main() {
-    let run_1_res = run_genalg(Option::None);
+    let args: Vec<String> = std::env::args().collect();
+
+    let benchmark = match arg_value(&args, "--benchmark") {
+        Some(value) => value.parse::<f32>().expect("the benchmark has to be a number"),
+        None => 0.0,
+    };
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--verify") {
+        match (args.get(idx + 1), args.get(idx + 2)) {
+            (Some(instance_spec), Some(routes_path)) => run_verify(instance_spec, routes_path, benchmark),
+            _ => {
+                eprintln!("usage: --verify <instance> <routes file>");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let instance = InstanceSource::parse(&arg_value(&args, "--instance").unwrap_or(String::from("9")), benchmark);
+    let mut environment = load_or_exit(&instance);
+
+    let spec = arg_value(&args, "--cost-model").unwrap_or(String::from("hard"));
+    environment.cost_model = match CostModel::parse(&spec) {
+        Some(cost_model) => cost_model,
+        None => {
+            eprintln!("not a cost model: {}", spec);
+            std::process::exit(1);
+        }
+    };
+    let adaptive_penalty = if args.iter().any(|arg| arg == "--adaptive-penalties") {
+        Option::from(AdaptivePenaltyConfig::hgs())
+    } else {
+        None
+    };
+
+    let mut mapping = None;
+    if let Some(spec) = arg_value(&args, "--subset") {
+        let selection = match PatientSelection::parse(&spec) {
+            Some(selection) => selection,
+            None => {
+                eprintln!("not a patient selection: {}", spec);
+                std::process::exit(1);
+            }
+        };
+        let (subset, subset_mapping) = subset_instance(&environment, &selection);
+        environment = subset;
+        mapping = Option::from(subset_mapping);
+    }
+
+    if let Some(path) = arg_value(&args, "--save") {
+        if let Err(e) = environment.save_json(&path) {
+            eprintln!("could not write {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("saved {} to {}", environment.set_name, path);
+        return;
+    }
+
+    if args.iter().any(|arg| arg == "--nsga2") {
+        run_multi_objective(&environment);
+        return;
+    }
+
+    // the ga always finds something, so say up front when the instance can not be solved
+    print!("{}", analyse_feasibility(&environment));
+
+    let run_1_res = run_genalg(Option::None, instance, &environment, environment.cost_model.clone(), adaptive_penalty);
 
     let mut gen = run_1_res.last().unwrap();
     println!("DELIVERY:");
     println!("score: {:}",gen.travel_time.unwrap());
     println!("valid: {:}",gen.valid.unwrap());
+    if environment.cost_model.objective == ObjectiveMode::FewestNurses {
+        println!("fitness: {:}",gen.fitness.unwrap());
+    }
+    println!("lateness: {:}",gen.lateness.unwrap());
+    println!("break violations: {:}",gen.break_violations.unwrap());
     println!("as str: {:?}",gen);
-    println!("as delivery string: {:?}",gen.get_as_delivery_str() );
+    if environment.depots.len() > 1 {
+        println!("as delivery string (depot, route): {:?}",gen.get_as_depot_delivery_str(&environment) );
+    } else {
+        println!("as delivery string: {:?}",gen.get_as_delivery_str(&environment) );
+    }
+    if environment.allows_dropped_patients() {
+        println!("dropped patients: {:?}",gen.get_dropped_patients(&environment) );
+    }
+    if let Some(mapping) = &mapping {
+        println!("in original ids: {:?}",mapping.routes_to_original(&gen.get_as_delivery_str(&environment)) );
+    }
+    if environment.benchmark > 0.0 {
+        println!("benchmark: {:}, gap: {:.2}%", environment.benchmark, (gen.travel_time.unwrap() / environment.benchmark - 1.0) * 100.0);
+    }
 
     // let mut best = gen.clone();
     // loop{
@@ -0,0 +1,457 @@
+use crate::genalg::{Genotype, NurseStop};
+use crate::train_data_parsing::EnvPruned;
+
+/// The timing summary of a sequence of stops, two summaries join in constant time. This is the
+/// time warp bookkeeping from Vidal et al. (2013): a stop reached after its window closes is served
+/// at the latest start and the difference is counted as time warp instead of pushing the rest of
+/// the route later.
+///
+/// It only holds for travel times that do not depend on the time of day and one window per stop,
+/// patients with several windows are given the span from the first start to the last end. Breaks
+/// and synchronized starts are ignored, moves picked with it have to be checked with the full
+/// evaluation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteSegment {
+    // travel indexes of the first and the last stop
+    pub first: i32,
+    pub last: i32,
+
+    // travel, care and waiting time when started at earliest_start
+    pub duration: f32,
+    pub time_warp: f32,
+
+    // the window the first stop can be started in without extra waiting or time warp
+    pub earliest_start: f32,
+    pub latest_start: f32,
+
+    pub travel_time: f32,
+    pub load: i32,
+}
+
+impl RouteSegment {
+    /// The depot of a nurse, both the start and the end of a route
+    pub fn depot(env: &EnvPruned, nurse_idx: usize) -> RouteSegment {
+        let nurse = env.nurses.get(nurse_idx).unwrap();
+        return RouteSegment {
+            first: env.get_nurse_depot_index(nurse_idx),
+            last: env.get_nurse_depot_index(nurse_idx),
+            duration: 0.0,
+            time_warp: 0.0,
+            earliest_start: nurse.start_time as f32,
+            latest_start: nurse.return_time as f32,
+            travel_time: 0.0,
+            load: 0,
+        };
+    }
+
+    /// A single visit, the second nurse at a synchronized patient carries no load
+    pub fn stop(env: &EnvPruned, stop: &NurseStop) -> RouteSegment {
+        let (patient_id, is_partner) = match stop {
+            NurseStop::Patient(id) => (*id, false),
+            NurseStop::SyncPartner(id) => (*id, true),
+            NurseStop::Depot => panic!("a depot is not a visit"),
+        };
+        let patient = env.patients.get((patient_id - 1) as usize).unwrap();
+        return RouteSegment {
+            first: patient.travel_index as i32,
+            last: patient.travel_index as i32,
+            duration: patient.care_time as f32,
+            time_warp: 0.0,
+            earliest_start: patient.start_time as f32,
+            latest_start: (patient.end_time - patient.care_time) as f32,
+            travel_time: 0.0,
+            load: if is_partner { 0 } else { patient.demand },
+        };
+    }
+
+    /// This sequence followed by the other one
+    pub fn join(&self, other: &RouteSegment, env: &EnvPruned) -> RouteSegment {
+        let travel = *env.get_travel_time_between(&self.last, &other.first);
+        let delta = self.duration - self.time_warp + travel;
+        let extra_wait = (other.earliest_start - delta - self.latest_start).max(0.0);
+        let extra_warp = (self.earliest_start + delta - other.latest_start).max(0.0);
+
+        return RouteSegment {
+            first: self.first,
+            last: other.last,
+            duration: self.duration + other.duration + travel + extra_wait,
+            time_warp: self.time_warp + other.time_warp + extra_warp,
+            earliest_start: (other.earliest_start - delta).max(self.earliest_start) - extra_wait,
+            latest_start: (other.latest_start - delta).min(self.latest_start) + extra_warp,
+            travel_time: self.travel_time + other.travel_time + travel,
+            load: self.load + other.load,
+        };
+    }
+}
+
+/// How much a minute of time warp and a unit above the capacity count next to a minute of travel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveWeights {
+    pub time_warp: f32,
+    pub excess_load: f32,
+}
+
+impl Default for MoveWeights {
+    fn default() -> Self {
+        return MoveWeights {
+            time_warp: 10.0,
+            excess_load: 10.0,
+        };
+    }
+}
+
+/// Join segments from left to right
+fn join_all(env: &EnvPruned, segments: &[RouteSegment]) -> RouteSegment {
+    let mut joined = *segments.first().unwrap();
+    for segment in &segments[1..] {
+        joined = joined.join(segment, env);
+    }
+    return joined;
+}
+
+/// The cached segments of one route. Node 0 and node len + 1 are the depot, node p + 1 is the
+/// visit at position p of the route.
+#[derive(Debug, Clone)]
+pub struct RouteData {
+    pub nurse_idx: usize,
+    pub stops: Vec<NurseStop>,
+    pub cost: f32,
+
+    // forward[i][j - i] is the segment of nodes i..=j
+    forward: Vec<Vec<RouteSegment>>,
+
+    // backward[i][j - i] is the segment of nodes j down to i
+    backward: Vec<Vec<RouteSegment>>,
+}
+
+impl RouteData {
+    pub fn new(env: &EnvPruned, nurse_idx: usize, stops: &[NurseStop], weights: &MoveWeights) -> RouteData {
+        let depot = RouteSegment::depot(env, nurse_idx);
+        let mut nodes: Vec<RouteSegment> = Vec::with_capacity(stops.len() + 2);
+        nodes.push(depot);
+        nodes.extend(stops.iter().map(|s| RouteSegment::stop(env, s)));
+        nodes.push(depot);
+
+        let mut forward: Vec<Vec<RouteSegment>> = Vec::with_capacity(nodes.len());
+        let mut backward: Vec<Vec<RouteSegment>> = Vec::with_capacity(nodes.len());
+        for i in 0..nodes.len() {
+            let mut fwd = vec![nodes[i]];
+            let mut bwd = vec![nodes[i]];
+            for node in &nodes[(i + 1)..] {
+                fwd.push(fwd.last().unwrap().join(node, env));
+                bwd.push(node.join(bwd.last().unwrap(), env));
+            }
+            forward.push(fwd);
+            backward.push(bwd);
+        }
+
+        let mut route = RouteData {
+            nurse_idx,
+            stops: stops.to_vec(),
+            cost: 0.0,
+            forward,
+            backward,
+        };
+        route.cost = route.segment_cost(env, &route.segment(0, stops.len() + 1), weights);
+        return route;
+    }
+
+    /// Number of visits on the route
+    pub fn len(&self) -> usize {
+        return self.stops.len();
+    }
+
+    /// Nodes i..=j in route order
+    pub fn segment(&self, i: usize, j: usize) -> RouteSegment {
+        return self.forward[i][j - i];
+    }
+
+    /// Nodes i..=j in reverse order
+    pub fn reversed(&self, i: usize, j: usize) -> RouteSegment {
+        return self.backward[i][j - i];
+    }
+
+    /// The depot and the route up to node i, its earliest finish is the forward arrival time
+    pub fn prefix(&self, i: usize) -> RouteSegment {
+        return self.segment(0, i);
+    }
+
+    /// The route from node i back to the depot, its latest start is the backward slack bound
+    pub fn suffix(&self, i: usize) -> RouteSegment {
+        return self.segment(i, self.len() + 1);
+    }
+
+    /// The travel time plus weighted time warp and excess load of a full route for this nurse
+    pub fn segment_cost(&self, env: &EnvPruned, route: &RouteSegment, weights: &MoveWeights) -> f32 {
+        let capacity = env.nurses.get(self.nurse_idx).unwrap().capacity;
+        return route.travel_time
+            + weights.time_warp * route.time_warp
+            + weights.excess_load * (route.load - capacity).max(0) as f32;
+    }
+}
+
+/// The cached route data of a genome, scores moves without changing the genome
+#[derive(Debug, Clone)]
+pub struct SolutionData {
+    pub routes: Vec<RouteData>,
+    pub weights: MoveWeights,
+}
+
+impl SolutionData {
+    /// Cache the routes driven by a nurse, the pool of dropped patients and routes without a nurse
+    /// are left out
+    pub fn new(env: &EnvPruned, genome: &Genotype, weights: MoveWeights) -> SolutionData {
+        let routes = genome
+            .stops
+            .split(|s| *s == NurseStop::Depot)
+            .take(env.nurses.len())
+            .enumerate()
+            .map(|(nurse_idx, stops)| RouteData::new(env, nurse_idx, stops, &weights))
+            .collect();
+        return SolutionData { routes, weights };
+    }
+
+    fn cost_of(&self, env: &EnvPruned, route_idx: usize, segments: &[RouteSegment]) -> f32 {
+        let route = self.routes.get(route_idx).unwrap();
+        return route.segment_cost(env, &join_all(env, segments), &self.weights);
+    }
+
+    /// Change in cost from taking the stop at a position out of its route
+    pub fn remove_delta(&self, env: &EnvPruned, route_idx: usize, pos: usize) -> f32 {
+        let route = self.routes.get(route_idx).unwrap();
+        let new_cost = self.cost_of(env, route_idx, &[route.prefix(pos), route.suffix(pos + 2)]);
+        return new_cost - route.cost;
+    }
+
+    /// Change in cost from moving the stop at from_pos to just before to_pos, both positions are
+    /// in the routes as they are now
+    pub fn relocate_delta(
+        &self,
+        env: &EnvPruned,
+        from_route: usize,
+        from_pos: usize,
+        to_route: usize,
+        to_pos: usize,
+    ) -> f32 {
+        let from = self.routes.get(from_route).unwrap();
+        let (a, b) = (from_pos + 1, to_pos + 1);
+        let moved = from.segment(a, a);
+
+        if from_route != to_route {
+            let to = self.routes.get(to_route).unwrap();
+            let to_cost = self.cost_of(env, to_route, &[to.prefix(b - 1), moved, to.suffix(b)]);
+            return self.remove_delta(env, from_route, from_pos) + to_cost - to.cost;
+        }
+
+        let end = from.len() + 1;
+        let new_cost = if b == a || b == a + 1 {
+            from.cost
+        } else if b < a {
+            self.cost_of(
+                env,
+                from_route,
+                &[from.prefix(b - 1), moved, from.segment(b, a - 1), from.suffix(a + 1)],
+            )
+        } else {
+            self.cost_of(
+                env,
+                from_route,
+                &[from.prefix(a - 1), from.segment(a + 1, b - 1), moved, from.segment(b, end)],
+            )
+        };
+        return new_cost - from.cost;
+    }
+
+    /// Change in cost from swapping the stops at two positions
+    pub fn swap_delta(&self, env: &EnvPruned, route_1: usize, pos_1: usize, route_2: usize, pos_2: usize) -> f32 {
+        if route_1 == route_2 {
+            if pos_1 == pos_2 {
+                return 0.0;
+            }
+            let route = self.routes.get(route_1).unwrap();
+            let (a, b) = (pos_1.min(pos_2) + 1, pos_1.max(pos_2) + 1);
+            let mut segments = vec![route.prefix(a - 1), route.segment(b, b)];
+            if b > a + 1 {
+                segments.push(route.segment(a + 1, b - 1));
+            }
+            segments.push(route.segment(a, a));
+            segments.push(route.suffix(b + 1));
+            return self.cost_of(env, route_1, &segments) - route.cost;
+        }
+
+        let first = self.routes.get(route_1).unwrap();
+        let second = self.routes.get(route_2).unwrap();
+        let (a, b) = (pos_1 + 1, pos_2 + 1);
+        let first_cost = self.cost_of(
+            env,
+            route_1,
+            &[first.prefix(a - 1), second.segment(b, b), first.suffix(a + 1)],
+        );
+        let second_cost = self.cost_of(
+            env,
+            route_2,
+            &[second.prefix(b - 1), first.segment(a, a), second.suffix(b + 1)],
+        );
+        return first_cost - first.cost + second_cost - second.cost;
+    }
+
+    /// Change in cost from reversing the stops at positions start..=end of a route
+    pub fn two_opt_delta(&self, env: &EnvPruned, route_idx: usize, start: usize, end: usize) -> f32 {
+        if start >= end {
+            return 0.0;
+        }
+        let route = self.routes.get(route_idx).unwrap();
+        let new_cost = self.cost_of(
+            env,
+            route_idx,
+            &[route.prefix(start), route.reversed(start + 1, end + 1), route.suffix(end + 2)],
+        );
+        return new_cost - route.cost;
+    }
+
+    /// Change in cost from putting the given stops at positions pos.. of a route, the order has to
+    /// hold the same stops as the positions it replaces
+    pub fn reorder_delta(&self, env: &EnvPruned, route_idx: usize, pos: usize, order: &[NurseStop]) -> f32 {
+        let route = self.routes.get(route_idx).unwrap();
+        let mut joined = route.prefix(pos);
+        for stop in order {
+            joined = joined.join(&RouteSegment::stop(env, stop), env);
+        }
+        joined = joined.join(&route.suffix(pos + order.len() + 1), env);
+        return route.segment_cost(env, &joined, &self.weights) - route.cost;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::evaluation::Violation;
+    use crate::genalg::{evaluate, generate_random_genome};
+    use crate::instance_generator::{generate_instance, GeneratorConfig, SpatialDistribution};
+    use crate::train_data_parsing::get_train_sett;
+
+    /// The cost of the changed routes built from scratch minus their cost before the move
+    fn rebuilt_delta(env: &EnvPruned, data: &SolutionData, changed: &[(usize, Vec<NurseStop>)]) -> f32 {
+        return changed
+            .iter()
+            .map(|(route_idx, stops)| {
+                RouteData::new(env, *route_idx, stops, &data.weights).cost - data.routes[*route_idx].cost
+            })
+            .sum();
+    }
+
+    fn assert_close(delta: f32, rebuilt: f32, total: f32, moved: &str) {
+        assert!((delta - rebuilt).abs() <= 1e-3 * total.max(1.0), "{}: {} vs {}", moved, delta, rebuilt);
+    }
+
+    #[test]
+    fn move_deltas_match_rebuilt_routes() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for set in [0, 4, 9] {
+            let env = get_train_sett(set);
+            for genome in generate_random_genome(&env, 3) {
+                let data = SolutionData::new(&env, &genome, MoveWeights::default());
+                let total: f32 = data.routes.iter().map(|r| r.cost).sum();
+                let used: Vec<usize> = (0..data.routes.len()).filter(|r| data.routes[*r].len() > 0).collect();
+                let stops = |route: usize| data.routes[route].stops.clone();
+
+                for _ in 0..100 {
+                    let r1 = used[rng.gen_range(0..used.len())];
+                    let r2 = used[rng.gen_range(0..used.len())];
+                    let p1 = rng.gen_range(0..data.routes[r1].len());
+                    let p2 = rng.gen_range(0..data.routes[r2].len());
+                    let to = rng.gen_range(0..data.routes.len());
+                    let to_pos = rng.gen_range(0..=data.routes[to].len());
+
+                    // relocate
+                    let val = data.routes[r1].stops[p1];
+                    let changed = if r1 == to {
+                        let mut route = stops(r1);
+                        route.insert(to_pos, val);
+                        route.remove(if to_pos <= p1 { p1 + 1 } else { p1 });
+                        vec![(r1, route)]
+                    } else {
+                        let mut from = stops(r1);
+                        from.remove(p1);
+                        let mut into = stops(to);
+                        into.insert(to_pos, val);
+                        vec![(r1, from), (to, into)]
+                    };
+                    let rebuilt = rebuilt_delta(&env, &data, &changed);
+                    assert_close(data.relocate_delta(&env, r1, p1, to, to_pos), rebuilt, total, "relocate");
+
+                    // swap
+                    let changed = if r1 == r2 {
+                        let mut route = stops(r1);
+                        route.swap(p1, p2);
+                        vec![(r1, route)]
+                    } else {
+                        let mut first = stops(r1);
+                        let mut second = stops(r2);
+                        std::mem::swap(&mut first[p1], &mut second[p2]);
+                        vec![(r1, first), (r2, second)]
+                    };
+                    let rebuilt = rebuilt_delta(&env, &data, &changed);
+                    assert_close(data.swap_delta(&env, r1, p1, r2, p2), rebuilt, total, "swap");
+
+                    // 2-opt
+                    let other = rng.gen_range(0..data.routes[r1].len());
+                    let (start, end) = (p1.min(other), p1.max(other));
+                    let mut reversed = stops(r1);
+                    reversed[start..=end].reverse();
+                    let rebuilt = rebuilt_delta(&env, &data, &[(r1, reversed)]);
+                    assert_close(data.two_opt_delta(&env, r1, start, end), rebuilt, total, "2-opt");
+
+                    // a new order of the visits start..=end
+                    let mut reordered = stops(r1);
+                    reordered[start..=end].shuffle(&mut rng);
+                    let order = reordered[start..=end].to_vec();
+                    let rebuilt = rebuilt_delta(&env, &data, &[(r1, reordered)]);
+                    assert_close(data.reorder_delta(&env, r1, start, &order), rebuilt, total, "reorder");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn segments_agree_with_the_full_evaluation() {
+        // one window per patient, no breaks and fixed travel times, where time warp is exact
+        let (mut on_time, mut late) = (0, 0);
+        for (seed, tightness) in [(1, 0.2), (2, 0.6), (3, 1.0)] {
+            let mut config = GeneratorConfig::new(seed, 40, SpatialDistribution::Random);
+            config.time_window_tightness = tightness;
+            let env = generate_instance(&config);
+
+            for genome in generate_random_genome(&env, 20) {
+                let data = SolutionData::new(&env, &genome, MoveWeights::default());
+                let report = evaluate(&env, &genome);
+                for (route, evaluated) in data.routes.iter().zip(&report.routes) {
+                    let whole = route.segment(0, route.len() + 1);
+                    assert!((whole.travel_time - evaluated.travel_time).abs() < 1e-2);
+                    assert_eq!(whole.load, evaluated.load);
+
+                    let in_time = !evaluated
+                        .violations
+                        .iter()
+                        .any(|v| matches!(v, Violation::TimeWindow { .. } | Violation::Overtime { .. }));
+                    assert_eq!(whole.time_warp < 1e-3, in_time, "{:?}", route.stops);
+                    if in_time {
+                        // leaving earlier than the segment says only adds waiting
+                        let back = whole.earliest_start + whole.duration;
+                        assert!((back - evaluated.return_time).abs() < 1e-2);
+                        let over_capacity = (evaluated.load - env.nurses[route.nurse_idx].capacity).max(0);
+                        assert!((route.cost - evaluated.travel_time - 10.0 * over_capacity as f32).abs() < 1e-2);
+                        on_time += 1;
+                    } else {
+                        late += 1;
+                    }
+                }
+            }
+        }
+        assert!(on_time > 0 && late > 0);
+    }
+}
@@ -7,7 +7,8 @@ use rand::{
 use rand::distributions::{WeightedError, WeightedIndex};
 use rand::seq::SliceRandom;
 use crate::genalg::NurseStop;
-use crate::{calculate_and_set_travel_time, EnvPruned, Genotype};
+use crate::move_evaluation::{MoveWeights, SolutionData};
+use crate::{EnvPruned, Genotype};
 use itertools::Itertools;
 
 
@@ -85,11 +86,22 @@ pub fn mutate(genome: &mut Genotype, env: &EnvPruned, itr: i32) {
     };
 
     let to: usize = if itr > 10000{
-        6
+        9
     }else {
-        5
+        8
     };
 
+    if env.allows_dropped_patients() && rng.gen::<f32>() < 0.2 {
+        // change which patients are visited instead of the order
+        if rng.gen::<bool>() {
+            drop_patient_mutate(genome, env);
+        } else {
+            reinsert_patient_mutate(genome, env);
+        }
+        repair_skill_mismatch(genome, env);
+        return;
+    }
+
     let val = rng.gen_range(0..to);//dist.sample(&mut rng);
     // let val = dist.sample(&mut rng);
     match val{
@@ -98,11 +110,135 @@ pub fn mutate(genome: &mut Genotype, env: &EnvPruned, itr: i32) {
         2 => scramble_mutate(genome),
         3 => inverse_mutation(genome),
         4 => move_seq_mutation(genome),
-        5 => brute_f_seg(genome,env),
+        5 => insert_optimal_mutate(genome, env),
+        6 => swap_optimal_mutate(genome, env),
+        7 => two_opt_optimal_mutate(genome, env),
+        8 => brute_f_seg(genome,env),
         _ => {
             panic!("invalid mut")
         }
     }
+    repair_skill_mismatch(genome, env);
+}
+
+/// The start and end (exclusive) index of route k in the stops
+fn route_bounds(stops: &Vec<NurseStop>, route_idx: usize) -> (usize, usize) {
+    let mut start = 0;
+    let mut route = 0;
+    for (idx, stop) in stops.iter().enumerate() {
+        if *stop == NurseStop::Depot {
+            if route == route_idx {
+                return (start, idx);
+            }
+            route += 1;
+            start = idx + 1;
+        }
+    }
+    return (start, stops.len());
+}
+
+/// Move a random visited patient to the dropped patients, both nurses are removed for patients that
+/// need two
+pub fn drop_patient_mutate(genome: &mut Genotype, env: &EnvPruned) {
+    let mut rng = rand::thread_rng();
+
+    let (_, pool_start) = route_bounds(&genome.stops, env.nurses.len() - 1);
+    let visited: Vec<usize> = (0..pool_start)
+        .filter(|idx| match genome.stops[*idx] {
+            NurseStop::Patient(patient_id) => env.patients.get((patient_id - 1) as usize).unwrap().drop_penalty.is_some(),
+            _ => false,
+        })
+        .collect();
+    let take = match visited.choose(&mut rng) {
+        Some(idx) => *idx,
+        None => return,
+    };
+
+    let val = genome.stops.remove(take);
+    genome.stops.push(val);
+
+    let partner = NurseStop::SyncPartner(val.patient_id().unwrap());
+    if let Some(partner_idx) = genome.stops.iter().position(|s| *s == partner) {
+        genome.stops.remove(partner_idx);
+        genome.stops.push(partner);
+    }
+}
+
+/// Move a random dropped patient to a random place on one of the routes, the partner visit of
+/// patients that need two nurses is moved as well
+pub fn reinsert_patient_mutate(genome: &mut Genotype, env: &EnvPruned) {
+    let mut rng = rand::thread_rng();
+
+    let (pool_start, pool_end) = route_bounds(&genome.stops, env.nurses.len());
+    let dropped: Vec<usize> = (pool_start..pool_end)
+        .filter(|idx| matches!(genome.stops[*idx], NurseStop::Patient(_)))
+        .collect();
+    let take = match dropped.choose(&mut rng) {
+        Some(idx) => *idx,
+        None => return,
+    };
+
+    let val = genome.stops.remove(take);
+    // the depot before the pool is at pool_start - 1, insert anywhere before it
+    let put = rng.gen_range(0..pool_start);
+    genome.stops.insert(put, val);
+
+    let partner = NurseStop::SyncPartner(val.patient_id().unwrap());
+    if let Some(partner_idx) = genome.stops.iter().position(|s| *s == partner) {
+        if partner_idx >= pool_start {
+            genome.stops.remove(partner_idx);
+            let put = rng.gen_range(0..pool_start);
+            genome.stops.insert(put, partner);
+        }
+    }
+}
+
+/// Move patients that are on a route whose nurse lacks the skills they need to a random route that
+/// can serve them. Patients no nurse can serve are left where they are.
+pub fn repair_skill_mismatch(genome: &mut Genotype, env: &EnvPruned) {
+    if !env.has_skill_requirements() {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+
+    let mut num_routes = genome.stops.iter().filter(|s| **s == NurseStop::Depot).count() + 1;
+    let pool_idx = if env.allows_dropped_patients() { Some(env.nurses.len()) } else { None };
+    if let Some(pool_idx) = pool_idx {
+        // dropped patients are never moved back onto a route here
+        num_routes = num_routes.min(pool_idx);
+    }
+    let qualified_routes = |patient_id: i32| -> Vec<usize> {
+        (0..num_routes).filter(|k| env.nurse_can_serve(*k, patient_id)).collect()
+    };
+
+    // both nurses at a synchronized visit need the skills
+    let mut misplaced = Vec::new();
+    let mut nurse_idx = 0;
+    let mut idx = 0;
+    while idx < genome.stops.len() {
+        let stop = genome.stops[idx];
+        match stop.patient_id() {
+            None => nurse_idx += 1,
+            Some(patient_id) => {
+                if Some(nurse_idx) != pool_idx
+                    && !env.nurse_can_serve(nurse_idx, patient_id)
+                    && !qualified_routes(patient_id).is_empty()
+                {
+                    genome.stops.remove(idx);
+                    misplaced.push(stop);
+                    continue;
+                }
+            }
+        }
+        idx += 1;
+    }
+
+    for stop in misplaced {
+        let route_idx = *qualified_routes(stop.patient_id().unwrap()).choose(&mut rng).unwrap();
+        let (start, end) = route_bounds(&genome.stops, route_idx);
+        let put = rng.gen_range(start..=end);
+        genome.stops.insert(put, stop);
+    }
 }
 
 /// Mutate the genome by swapping two points on the genome
@@ -171,44 +307,117 @@ pub fn move_seq_mutation(genome: &mut Genotype){
     genome.stops.append(&mut snip);
 }
 
+/// The route a stop of the genome is on and its position in that route
+fn route_position(stops: &Vec<NurseStop>, idx: usize) -> (usize, usize) {
+    let route_idx = stops[..idx].iter().filter(|s| **s == NurseStop::Depot).count();
+    let (start, _) = route_bounds(stops, route_idx);
+    return (route_idx, idx - start);
+}
+
+/// The index in the stops of every visit on a nurse route, the pool of dropped patients is left out
+fn routed_visits(genome: &Genotype, env: &EnvPruned) -> Vec<usize> {
+    let (_, routed_end) = route_bounds(&genome.stops, env.nurses.len() - 1);
+    return (0..routed_end)
+        .filter(|idx| genome.stops[*idx] != NurseStop::Depot)
+        .collect();
+}
+
+/// Mutate the genome by taking out a random visit and putting it back where it costs the least,
+/// every position is scored with the cached route segments instead of a full evaluation
 pub fn insert_optimal_mutate(genome: &mut Genotype, env: &EnvPruned) {
-    // -> Genotype{
     let mut rng = rand::thread_rng();
 
-    let take: usize = rng.gen_range(0..genome.stops.len());
+    let take = match routed_visits(genome, env).choose(&mut rng) {
+        Some(idx) => *idx,
+        None => return,
+    };
+    let (from_route, from_pos) = route_position(&genome.stops, take);
+
+    let data = SolutionData::new(env, genome, MoveWeights::default());
+    let mut best: Option<(f32, usize, usize)> = None;
+    for (route_idx, route) in data.routes.iter().enumerate() {
+        for pos in 0..=route.len() {
+            let delta = data.relocate_delta(env, from_route, from_pos, route_idx, pos);
+            if best.is_none() || delta < best.unwrap().0 {
+                best = Option::from((delta, route_idx, pos));
+            }
+        }
+    }
 
-    // let val = genome.stops.remove(take);
-
-    // genome.stops.swap(0,take);
-    let last = take;
-    let mut best_idx = take;
-    let mut best_travel_t = genome.travel_time.clone();
-    for n in (0..genome.stops.len()){
-        genome.stops.swap(last, n);
-        calculate_and_set_travel_time(env,genome);
-        if genome.travel_time < best_travel_t{
-            best_travel_t = genome.travel_time.clone()
-            best_idx = n
+    // the position is counted with the visit still on its route
+    let (_, route_idx, pos) = best.unwrap();
+    let val = genome.stops.remove(take);
+    let (route_start, _) = route_bounds(&genome.stops, route_idx);
+    let put = if route_idx == from_route && pos > from_pos { route_start + pos - 1 } else { route_start + pos };
+    genome.stops.insert(put, val);
+}
+
+/// Mutate the genome by swapping a random visit with the visit that gives the lowest cost, scored
+/// with the cached route segments
+pub fn swap_optimal_mutate(genome: &mut Genotype, env: &EnvPruned) {
+    let mut rng = rand::thread_rng();
+
+    let take = match routed_visits(genome, env).choose(&mut rng) {
+        Some(idx) => *idx,
+        None => return,
+    };
+    let (from_route, from_pos) = route_position(&genome.stops, take);
+
+    let data = SolutionData::new(env, genome, MoveWeights::default());
+    let mut best: Option<(f32, usize, usize)> = None;
+    for (route_idx, route) in data.routes.iter().enumerate() {
+        for pos in 0..route.len() {
+            let delta = data.swap_delta(env, from_route, from_pos, route_idx, pos);
+            if best.is_none() || delta < best.unwrap().0 {
+                best = Option::from((delta, route_idx, pos));
+            }
         }
+    }
 
+    let (_, route_idx, pos) = best.unwrap();
+    let (route_start, _) = route_bounds(&genome.stops, route_idx);
+    genome.stops.swap(take, route_start + pos);
+}
+
+/// Mutate the genome by reversing the part of a random route that gives the lowest cost, scored
+/// with the cached route segments
+pub fn two_opt_optimal_mutate(genome: &mut Genotype, env: &EnvPruned) {
+    let mut rng = rand::thread_rng();
 
-        genome.stops.sw
+    let data = SolutionData::new(env, genome, MoveWeights::default());
+    let routes: Vec<usize> = (0..data.routes.len()).filter(|r| data.routes[*r].len() > 1).collect();
+    let route_idx = match routes.choose(&mut rng) {
+        Some(route_idx) => *route_idx,
+        None => return,
+    };
+
+    let len = data.routes[route_idx].len();
+    let mut best: Option<(f32, usize, usize)> = None;
+    for start in 0..len {
+        for end in (start + 1)..len {
+            let delta = data.two_opt_delta(env, route_idx, start, end);
+            if best.is_none() || delta < best.unwrap().0 {
+                best = Option::from((delta, start, end));
+            }
+        }
     }
-    genome.stops.insert(put, val);
-    // return genome;
+
+    let (_, start, end) = best.unwrap();
+    let (route_start, _) = route_bounds(&genome.stops, route_idx);
+    genome.stops[(route_start + start)..=(route_start + end)].reverse();
 }
 
 
 fn get_seq_tt(seq: &Vec<&NurseStop>, env: &EnvPruned) -> f32{
 
     let mut last = match seq.first().unwrap(){
-        NurseStop::Patient(n) => n,
+        NurseStop::Patient(n) | NurseStop::SyncPartner(n) => n,
         NurseStop::Depot => &0,
     };
     let mut roll = 0.0;
     for stop in seq[1..seq.len()].iter(){
         let mut cur = match stop{
-            NurseStop::Patient(n) => n,
+            NurseStop::Patient(n) | NurseStop::SyncPartner(n) => n,
             NurseStop::Depot => &0,
         };
        roll += env.get_travel_time_between(last,cur) ;
@@ -218,58 +427,76 @@ fn get_seq_tt(seq: &Vec<&NurseStop>, env: &EnvPruned) -> f32{
     return roll;
 }
 
+/// Mutate the genome by trying every order of a few visits in a row on one route and keeping the
+/// order the cached route segments score the lowest
 pub fn brute_f_seg(genome: &mut Genotype, env: &EnvPruned){
     let mut rng = rand::thread_rng();
 
     let num_to_bf = rng.gen_range(2..=5);
 
-    let mut bf_slice: Vec<NurseStop> = Vec::new();
-    let mut start_p = 0;
-    loop{
-        start_p = rng.gen_range(0..(genome.stops.len()-num_to_bf as usize));
-        let slc = &genome.stops[(start_p as usize)..(start_p+num_to_bf as usize)];
-        if slc.contains(&NurseStop::Depot){
-            continue
-        }else {
-            bf_slice.append(&mut slc.clone().to_owned());
-            break
-        }
-    }
-    let mut tmp_genome = genome.clone();
-    if tmp_genome.travel_time.is_none(){
-        calculate_and_set_travel_time(&env, &mut tmp_genome)
-    }
-    let mut best_s_time = tmp_genome.travel_time.unwrap();
-    let init_best = best_s_time.clone();
-    let mut best_s: Option<Vec<NurseStop>> = Option::None;
-
-    // println!("\n########NEW");
-    // println!("{:?}", bf_slice);
-    // println!("{:?}", best_s_time);
-    // println!("{:?}", bf_slice.len());
-
-
-    for comb in bf_slice.iter().permutations(bf_slice.len()){
-        // let comb_t = get_seq_tt(&comb,env);
-
-        let a: Vec<NurseStop> = comb.iter().cloned().cloned().collect();
-        tmp_genome.stops.splice((start_p as usize)..(start_p+num_to_bf as usize), a.clone());
-        calculate_and_set_travel_time(&env, &mut tmp_genome);
-        let comb_t = tmp_genome.travel_time.unwrap();
-        // println!();
-        // println!("{:?}", comb);
-        // println!("{:?}", comb_t);
-        if comb_t < best_s_time{
-            best_s_time = comb_t;
-            // let a: Vec<NurseStop> = comb.iter().cloned().cloned().collect();
-            best_s.insert(a);
+    // the runs of visits on one nurse route that are long enough
+    let (_, routed_end) = route_bounds(&genome.stops, env.nurses.len() - 1);
+    let starts: Vec<usize> = (0..routed_end.saturating_sub(num_to_bf - 1))
+        .filter(|start| !genome.stops[*start..(start + num_to_bf)].contains(&NurseStop::Depot))
+        .collect();
+    let start_p = match starts.choose(&mut rng) {
+        Some(start_p) => *start_p,
+        None => return,
+    };
+    let (route_idx, pos) = route_position(&genome.stops, start_p);
+
+    let data = SolutionData::new(env, genome, MoveWeights::default());
+    let bf_slice = genome.stops[start_p..(start_p + num_to_bf)].to_vec();
+
+    // the first order is the current one, it is kept unless another is better
+    let mut best: Option<(f32, Vec<NurseStop>)> = None;
+    for order in bf_slice.into_iter().permutations(num_to_bf) {
+        let delta = data.reorder_delta(env, route_idx, pos, &order);
+        if best.is_none() || delta < best.as_ref().unwrap().0 {
+            best = Option::from((delta, order));
         }
     }
 
-    if best_s_time != init_best{
-        // println!("NEW BEST WOHOOOOOOOOOO imp {}", init_best - best_s_time);
-        genome.stops.splice((start_p as usize)..(start_p+num_to_bf as usize), best_s.unwrap());
-    }
+    let (_, order) = best.unwrap();
+    genome.stops.splice(start_p..(start_p + num_to_bf), order);
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genalg::generate_random_genome;
+    use crate::train_data_parsing::get_train_sett;
 
+    fn segment_cost(env: &EnvPruned, genome: &Genotype) -> f32 {
+        return SolutionData::new(env, genome, MoveWeights::default()).routes.iter().map(|r| r.cost).sum();
+    }
+
+    #[test]
+    fn scored_moves_keep_the_stops_and_never_pick_a_worse_order() {
+        let env = get_train_sett(0);
+        let operators: [(fn(&mut Genotype, &EnvPruned), bool); 4] = [
+            (insert_optimal_mutate, true),
+            (swap_optimal_mutate, true),
+            (two_opt_optimal_mutate, false),
+            (brute_f_seg, true),
+        ];
+        for genome in generate_random_genome(&env, 5) {
+            let mut sorted_stops = genome.stops.clone();
+            sorted_stops.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for (operator, can_stay) in operators {
+                let mut mutated = genome.clone();
+                for _ in 0..20 {
+                    let before = segment_cost(&env, &mutated);
+                    operator(&mut mutated, &env);
+                    if can_stay {
+                        // leaving the genome as it is is one of the moves scored
+                        assert!(segment_cost(&env, &mutated) <= before + 1e-2);
+                    }
+                }
+                let mut stops = mutated.stops.clone();
+                stops.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                assert_eq!(stops, sorted_stops);
+            }
+        }
+    }
 }
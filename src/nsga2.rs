@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use rand::Rng;
+
+use crate::crossover::simple_sub_path_crossover;
+use crate::evaluation::EvaluationReport;
+use crate::genalg::{calculate_and_get_report, generate_random_genome, Genotype};
+use crate::mutation::{mutate, repair_skill_mismatch};
+use crate::train_data_parsing::EnvPruned;
+
+/// The objectives NSGA-II minimizes at the same time. Genomes that break constraints are compared
+/// by the number of violations before the objectives, as in Deb's constrained domination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Objectives {
+    // with optional patients the drop penalties are added, so dropping everyone is not free
+    pub travel_time: f32,
+    pub waiting_time: f32,
+
+    // the longest minus the shortest route duration, only nurses with patients are counted
+    pub imbalance: f32,
+
+    pub violations: usize,
+}
+
+impl Objectives {
+    pub fn from_report(env: &EnvPruned, report: &EvaluationReport) -> Objectives {
+        let durations: Vec<f32> = report
+            .routes
+            .iter()
+            .filter(|r| r.num_visits > 0)
+            .map(|r| {
+                let nurse = env.nurses.get(r.nurse_idx).unwrap_or(env.nurses.last().unwrap());
+                r.return_time - nurse.start_time as f32
+            })
+            .collect();
+        let imbalance = if durations.is_empty() {
+            0.0
+        } else {
+            durations.iter().cloned().fold(f32::MIN, f32::max) - durations.iter().cloned().fold(f32::MAX, f32::min)
+        };
+
+        return Objectives {
+            travel_time: report.total_travel_time() + report.dropped_cost,
+            waiting_time: report.routes.iter().map(|r| r.waiting_time).sum(),
+            imbalance,
+            violations: report.all_violations().len(),
+        };
+    }
+
+    fn values(&self) -> [f32; 3] {
+        return [self.travel_time, self.waiting_time, self.imbalance];
+    }
+
+    /// True if self is at least as good in every objective and better in one, a genome with fewer
+    /// violations always dominates
+    pub fn dominates(&self, other: &Objectives) -> bool {
+        if self.violations != other.violations {
+            return self.violations < other.violations;
+        }
+        let (a, b) = (self.values(), other.values());
+        return a.iter().zip(b.iter()).all(|(x, y)| x <= y) && a.iter().zip(b.iter()).any(|(x, y)| x < y);
+    }
+}
+
+impl Display for Objectives {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "travel {:>9.2}, waiting {:>9.2}, imbalance {:>8.2}, violations {}",
+            self.travel_time, self.waiting_time, self.imbalance, self.violations
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Individual {
+    pub genome: Genotype,
+    pub objectives: Objectives,
+
+    // 0 is the non dominated front
+    pub rank: usize,
+    pub crowding_distance: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nsga2Config {
+    pub pop_size: usize,
+    pub generations: usize,
+    pub crossover_chance: f32,
+    pub mutation_chance: f32,
+}
+
+impl Default for Nsga2Config {
+    fn default() -> Self {
+        return Nsga2Config {
+            pop_size: 200,
+            generations: 2000,
+            crossover_chance: 0.5,
+            mutation_chance: 0.8,
+        };
+    }
+}
+
+/// Split the population into fronts, front k is dominated only by genomes in the fronts before it
+pub fn non_dominated_sort(objectives: &[Objectives]) -> Vec<Vec<usize>> {
+    let n = objectives.len();
+    let mut dominated_by: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut domination_count: Vec<usize> = vec![0; n];
+
+    for i in 0..n {
+        for j in (i + 1)..n {
+            if objectives[i].dominates(&objectives[j]) {
+                dominated_by[i].push(j);
+                domination_count[j] += 1;
+            } else if objectives[j].dominates(&objectives[i]) {
+                dominated_by[j].push(i);
+                domination_count[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts: Vec<Vec<usize>> = Vec::new();
+    let mut current: Vec<usize> = (0..n).filter(|i| domination_count[*i] == 0).collect();
+    while !current.is_empty() {
+        let mut next = Vec::new();
+        for i in &current {
+            for j in &dominated_by[*i] {
+                domination_count[*j] -= 1;
+                if domination_count[*j] == 0 {
+                    next.push(*j);
+                }
+            }
+        }
+        fronts.push(current);
+        current = next;
+    }
+    return fronts;
+}
+
+/// The crowding distance of every member of a front, in the order of the front. The ends of the
+/// front in every objective get an infinite distance so they are always kept.
+pub fn crowding_distances(objectives: &[Objectives], front: &[usize]) -> Vec<f32> {
+    let mut distances = vec![0.0; front.len()];
+    if front.len() <= 2 {
+        return vec![f32::INFINITY; front.len()];
+    }
+
+    for objective in 0..3 {
+        let value = |pos: usize| objectives[front[pos]].values()[objective];
+        let mut order: Vec<usize> = (0..front.len()).collect();
+        order.sort_by(|a, b| value(*a).total_cmp(&value(*b)));
+
+        let (min, max) = (value(order[0]), value(*order.last().unwrap()));
+        distances[order[0]] = f32::INFINITY;
+        distances[*order.last().unwrap()] = f32::INFINITY;
+        if max - min <= 0.0 {
+            continue;
+        }
+        for k in 1..(order.len() - 1) {
+            distances[order[k]] += (value(order[k + 1]) - value(order[k - 1])) / (max - min);
+        }
+    }
+    return distances;
+}
+
+/// Set the rank and crowding distance of everyone, best first
+fn rank_population(population: &mut Vec<Individual>) {
+    let objectives: Vec<Objectives> = population.iter().map(|i| i.objectives).collect();
+    for (rank, front) in non_dominated_sort(&objectives).iter().enumerate() {
+        let distances = crowding_distances(&objectives, front);
+        for (pos, idx) in front.iter().enumerate() {
+            let individual = population.get_mut(*idx).unwrap();
+            individual.rank = rank;
+            individual.crowding_distance = distances[pos];
+        }
+    }
+    population.sort_by(crowded_compare);
+}
+
+/// Lower rank first, then the less crowded
+fn crowded_compare(a: &Individual, b: &Individual) -> Ordering {
+    return a
+        .rank
+        .cmp(&b.rank)
+        .then_with(|| b.crowding_distance.total_cmp(&a.crowding_distance));
+}
+
+fn crowded_tournament<'a>(population: &'a Vec<Individual>) -> &'a Individual {
+    let mut rng = rand::thread_rng();
+    let a = population.get(rng.gen_range(0..population.len())).unwrap();
+    let b = population.get(rng.gen_range(0..population.len())).unwrap();
+    return if crowded_compare(a, b) == Ordering::Greater { b } else { a };
+}
+
+fn evaluate_individual(env: &EnvPruned, mut genome: Genotype) -> Individual {
+    let report = calculate_and_get_report(env, &mut genome);
+    return Individual {
+        genome,
+        objectives: Objectives::from_report(env, &report),
+        rank: 0,
+        crowding_distance: 0.0,
+    };
+}
+
+fn make_child(env: &EnvPruned, config: &Nsga2Config, parent_1: &Genotype, parent_2: &Genotype, generation: i32) -> Genotype {
+    let mut rng = rand::thread_rng();
+    let mut child = if config.crossover_chance > rng.gen::<f32>() {
+        simple_sub_path_crossover(parent_1, parent_2)
+    } else {
+        Genotype::new(parent_1.stops.clone(), parent_1.meta_genes.clone())
+    };
+    repair_skill_mismatch(&mut child, env);
+    if config.mutation_chance > rng.gen::<f32>() {
+        mutate(&mut child, env, generation);
+    }
+    return child;
+}
+
+/// Run NSGA-II with the ga operators and return the final Pareto front, one genome per distinct
+/// set of objectives and sorted by travel time
+pub fn run_nsga2(env: &EnvPruned, config: &Nsga2Config) -> Vec<Individual> {
+    let mut population: Vec<Individual> = generate_random_genome(env, config.pop_size as i32)
+        .into_iter()
+        .map(|genome| evaluate_individual(env, genome))
+        .collect();
+    rank_population(&mut population);
+
+    for generation in 0..config.generations {
+        let mut offspring = Vec::with_capacity(config.pop_size);
+        while offspring.len() < config.pop_size {
+            let parent_1 = crowded_tournament(&population);
+            let parent_2 = crowded_tournament(&population);
+            let child = make_child(env, config, &parent_1.genome, &parent_2.genome, generation as i32);
+            offspring.push(evaluate_individual(env, child));
+        }
+
+        // parents and children compete, the fronts are filled in order and the last one that
+        // does not fit is cut by crowding distance
+        population.append(&mut offspring);
+        rank_population(&mut population);
+        population.truncate(config.pop_size);
+    }
+
+    let mut front: Vec<Individual> = population.into_iter().filter(|i| i.rank == 0).collect();
+    front.sort_by(|a, b| a.objectives.travel_time.total_cmp(&b.objectives.travel_time));
+    front.dedup_by(|a, b| a.objectives == b.objectives);
+    return front;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genalg::evaluate;
+    use crate::genalg::tests::{genome_with_dropped, optional_patients_env};
+
+    fn objectives(travel_time: f32, waiting_time: f32, imbalance: f32, violations: usize) -> Objectives {
+        return Objectives { travel_time, waiting_time, imbalance, violations };
+    }
+
+    #[test]
+    fn fronts_are_dominated_only_by_earlier_fronts() {
+        let population = vec![
+            objectives(1.0, 1.0, 1.0, 0),
+            objectives(2.0, 2.0, 2.0, 0),
+            objectives(1.0, 3.0, 0.0, 0),
+            objectives(3.0, 3.0, 3.0, 0),
+            // better in every objective but breaks a constraint
+            objectives(0.0, 0.0, 0.0, 1),
+        ];
+        let fronts = non_dominated_sort(&population);
+        assert_eq!(fronts, vec![vec![0, 2], vec![1], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn the_ends_of_a_front_are_kept_and_crowded_members_rank_lower() {
+        let population = vec![
+            objectives(0.0, 4.0, 0.0, 0),
+            objectives(1.0, 2.0, 0.0, 0),
+            objectives(3.0, 1.0, 0.0, 0),
+            objectives(4.0, 0.0, 0.0, 0),
+        ];
+        let distances = crowding_distances(&population, &[0, 1, 2, 3]);
+        assert_eq!(distances[0], f32::INFINITY);
+        assert_eq!(distances[3], f32::INFINITY);
+        assert!((distances[1] - 1.5).abs() < 1e-6);
+        assert!((distances[2] - 1.25).abs() < 1e-6);
+
+        assert_eq!(crowding_distances(&population, &[1, 2]), vec![f32::INFINITY; 2]);
+    }
+
+    #[test]
+    fn dropping_every_patient_is_not_free() {
+        let env = optional_patients_env();
+        let genome = genome_with_dropped(&env, env.patients.len());
+
+        let objectives = Objectives::from_report(&env, &evaluate(&env, &genome));
+        assert_eq!(objectives.travel_time, 100.0 * env.patients.len() as f32);
+        assert_eq!(objectives.waiting_time, 0.0);
+        assert_eq!(objectives.imbalance, 0.0);
+    }
+}
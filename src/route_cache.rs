@@ -0,0 +1,283 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::rc::Rc;
+
+use crate::cost_model::CostModel;
+use crate::genalg::{NurseStop, RouteEvaluation};
+use crate::train_data_parsing::EnvPruned;
+
+/// How often the cache had the route already
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        return self.hits as f64 / lookups as f64;
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} routes cached",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0,
+            self.entries
+        );
+    }
+}
+
+/// A multiply and rotate hash. The default hasher resists collision attacks and is slow on the few
+/// stops of a route, which are hashed for every route of every genome
+#[derive(Debug, Clone, Copy, Default)]
+struct RouteHasher {
+    hash: u64,
+}
+
+impl Hasher for RouteHasher {
+    fn finish(&self) -> u64 {
+        return self.hash;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(*byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ value).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write_u64(value as u32 as u64);
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+type RouteMap = HashMap<Vec<NurseStop>, Rc<RouteEvaluation>, BuildHasherDefault<RouteHasher>>;
+
+/// What the cached routes were evaluated with. Comparing the whole instance for every genome costs
+/// more than the cache saves, so instances are told apart by where they live, their size and the
+/// cost model. An instance whose patients or travel times are edited in place looks the same, the
+/// caller has to clear the cache after such an edit.
+#[derive(Debug, Clone, PartialEq)]
+struct InstanceKey {
+    address: usize,
+    travel_matrix: usize,
+    set_name: String,
+    patients: usize,
+    nurses: usize,
+    cost_model: CostModel,
+}
+
+impl InstanceKey {
+    fn of(env: &EnvPruned) -> InstanceKey {
+        return InstanceKey {
+            address: env as *const EnvPruned as usize,
+            travel_matrix: env.travel_matrix.as_ptr() as usize,
+            set_name: env.set_name.clone(),
+            patients: env.patients.len(),
+            nurses: env.nurses.len(),
+            cost_model: env.cost_model.clone(),
+        };
+    }
+
+    /// Without building a key, this runs for every genome
+    fn matches(&self, env: &EnvPruned) -> bool {
+        return self.address == env as *const EnvPruned as usize
+            && self.travel_matrix == env.travel_matrix.as_ptr() as usize
+            && self.patients == env.patients.len()
+            && self.nurses == env.nurses.len()
+            && self.cost_model == env.cost_model
+            && self.set_name == env.set_name;
+    }
+}
+
+/// Evaluated routes keyed by the nurse and the ordered stops. The entries are only valid for one
+/// instance and cost model, [RouteCache::check_env] empties the cache when it is used with another
+/// one. A hit hands out the shared evaluation and does not allocate.
+#[derive(Debug)]
+pub struct RouteCache {
+    // one map per nurse so a route can be looked up by its slice
+    routes: Vec<RouteMap>,
+    entries: usize,
+
+    instance: Option<InstanceKey>,
+
+    // the cache is emptied when it grows past this
+    max_entries: usize,
+
+    hits: u64,
+    misses: u64,
+}
+
+impl RouteCache {
+    pub fn new(max_entries: usize) -> RouteCache {
+        return RouteCache {
+            routes: Vec::new(),
+            entries: 0,
+            instance: None,
+            max_entries,
+            hits: 0,
+            misses: 0,
+        };
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        return CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries,
+        };
+    }
+
+    /// Forget every route, needed after the instance the cache is used with was edited in place
+    pub fn clear(&mut self) {
+        self.routes.clear();
+        self.entries = 0;
+    }
+
+    /// Drop the entries if they were made for another instance or cost model. Called once per
+    /// genome, before its routes are looked up.
+    pub fn check_env(&mut self, env: &EnvPruned) {
+        if !self.instance.as_ref().is_some_and(|instance| instance.matches(env)) {
+            self.clear();
+            self.instance = Option::from(InstanceKey::of(env));
+        }
+    }
+
+    /// The cached evaluation of the route, or evaluate it and remember the result
+    pub fn get_or_evaluate<F>(&mut self, nurse_idx: usize, route: &[NurseStop], evaluate: F) -> Rc<RouteEvaluation>
+    where
+        F: FnOnce() -> RouteEvaluation,
+    {
+        if let Some(cached) = self.routes.get(nurse_idx).and_then(|routes| routes.get(route)) {
+            self.hits += 1;
+            return Rc::clone(cached);
+        }
+
+        self.misses += 1;
+        let evaluation = Rc::new(evaluate());
+        if self.entries >= self.max_entries {
+            self.clear();
+        }
+        if self.routes.len() <= nurse_idx {
+            self.routes.resize_with(nurse_idx + 1, RouteMap::default);
+        }
+        self.routes.get_mut(nurse_idx).unwrap().insert(route.to_vec(), Rc::clone(&evaluation));
+        self.entries += 1;
+        return evaluation;
+    }
+}
+
+impl Default for RouteCache {
+    fn default() -> Self {
+        return RouteCache::new(200_000);
+    }
+}
+
+thread_local! {
+    // every worker thread keeps its own cache, the threads evaluate different populations
+    static THREAD_CACHE: RefCell<RouteCache> = RefCell::new(RouteCache::default());
+}
+
+/// Run f with the route cache of the current thread
+pub fn with_thread_cache<T, F: FnOnce(&mut RouteCache) -> T>(f: F) -> T {
+    return THREAD_CACHE.with(|cache| f(&mut cache.borrow_mut()));
+}
+
+/// The hit and miss counts of the route cache of the current thread
+pub fn thread_cache_stats() -> CacheStats {
+    return with_thread_cache(|cache| cache.stats());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    use crate::cost_model::CostFunction;
+    use crate::genalg::{evaluate, evaluate_with_cache, generate_random_genome};
+    use crate::instance_subset::{subset_instance, PatientSelection};
+    use crate::train_data_parsing::get_train_sett;
+
+    #[test]
+    fn same_named_instances_do_not_share_routes() {
+        let env = get_train_sett(0);
+        let (first, _) = subset_instance(&env, &PatientSelection::RandomK { k: 10, seed: 1 });
+        let (mut second, _) = subset_instance(&env, &PatientSelection::RandomK { k: 10, seed: 2 });
+        second.set_name = first.set_name.clone();
+
+        let mut cache = RouteCache::default();
+        for genome in generate_random_genome(&first, 5) {
+            for instance in [&first, &second, &first] {
+                let cached = evaluate_with_cache(instance, &genome, Option::from(&mut cache));
+                assert_eq!(cached.fitness(), evaluate(instance, &genome).fitness());
+            }
+        }
+
+        // a new cost model is seen, an instance edited in place has to be cleared by the caller
+        let genome = generate_random_genome(&second, 1).pop().unwrap();
+        evaluate_with_cache(&second, &genome, Option::from(&mut cache));
+        second.cost_model.penalty.time_window = CostFunction::Constant(0.5);
+        let cached = evaluate_with_cache(&second, &genome, Option::from(&mut cache));
+        assert_eq!(cached.fitness(), evaluate(&second, &genome).fitness());
+
+        for travel_time in second.travel_matrix.iter_mut() {
+            *travel_time *= 2.0;
+        }
+        cache.clear();
+        let cached = evaluate_with_cache(&second, &genome, Option::from(&mut cache));
+        assert_eq!(cached.fitness(), evaluate(&second, &genome).fitness());
+    }
+
+    #[test]
+    fn cache_hits_are_faster_than_driving_the_routes() {
+        let env = get_train_sett(9);
+        let genomes = generate_random_genome(&env, 2000);
+
+        let mut cache = RouteCache::default();
+        for genome in &genomes {
+            evaluate_with_cache(&env, genome, Option::from(&mut cache));
+        }
+        let misses = cache.stats().misses;
+
+        let start = Instant::now();
+        let driven: Vec<f32> = genomes.iter().map(|genome| evaluate(&env, genome).fitness()).collect();
+        let driving = start.elapsed();
+
+        let start = Instant::now();
+        let cached: Vec<f32> =
+            genomes.iter().map(|genome| evaluate_with_cache(&env, genome, Option::from(&mut cache)).fitness()).collect();
+        let looking_up = start.elapsed();
+
+        assert_eq!(cached, driven);
+        assert_eq!(cache.stats().misses, misses);
+        assert!(looking_up < driving, "cache hits took {:?}, evaluating took {:?}", looking_up, driving);
+    }
+}
@@ -1,16 +1,116 @@
-use std::collections::HashMap;
-use std::fs;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+use crate::breaks::{BreakRules, LunchBreak};
+use crate::cost_model::CostModel;
+use crate::instance_generator::{generate_instance, GeneratorConfig};
+
+/// The ways loading an instance can fail
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Json(serde_json::Error),
+
+    // a solomon file that does not follow the expected layout
+    Format { line: usize, reason: String },
+
+    // the patients has to be keyed 1..=n
+    NonContiguousPatientIds { missing_id: usize },
+
+    // the travel matrix has to have one row and column for every depot and patient
+    TravelMatrixSize { expected: usize, found: usize },
+    TravelMatrixRowSize { row: usize, expected: usize, found: usize },
+
+    // when nurses are listed there has to be one entry per nurse
+    NurseCount { expected: usize, found: usize },
+
+    // a nurse starts from a depot that is not in the instance
+    UnknownDepot { nurse: usize, depot: usize },
+
+    // skills are stored as bit flags so there can be at most 64 different ones
+    TooManySkills { found: usize },
+
+    // the time windows of a patient has to be sorted, non overlapping and not empty
+    InvalidTimeWindows { patient_id: usize },
+
+    // the travel time periods has to be sorted by their start time
+    UnsortedTravelPeriods { period: usize },
+
+    // a break rule that can never be followed
+    InvalidBreakRules { reason: String },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "could not read the instance: {}", e),
+            LoadError::Json(e) => write!(f, "could not parse the instance: {}", e),
+            LoadError::Format { line, reason } => write!(f, "line {}: {}", line, reason),
+            LoadError::NonContiguousPatientIds { missing_id } => {
+                write!(f, "patient ids are not contiguous, patient {} is missing", missing_id)
+            }
+            LoadError::TravelMatrixSize { expected, found } => {
+                write!(f, "travel matrix has {} rows, expected {}", found, expected)
+            }
+            LoadError::TravelMatrixRowSize { row, expected, found } => {
+                write!(f, "travel matrix row {} has {} columns, expected {}", row, found, expected)
+            }
+            LoadError::NurseCount { expected, found } => {
+                write!(f, "{} nurses are listed, expected {}", found, expected)
+            }
+            LoadError::UnknownDepot { nurse, depot } => {
+                write!(f, "nurse {} starts from depot {} which does not exist", nurse, depot)
+            }
+            LoadError::TooManySkills { found } => {
+                write!(f, "the instance uses {} different skills, at most {} are supported", found, MAX_SKILLS)
+            }
+            LoadError::InvalidTimeWindows { patient_id } => {
+                write!(f, "the time windows of patient {} are not sorted and disjoint", patient_id)
+            }
+            LoadError::UnsortedTravelPeriods { period } => {
+                write!(f, "travel period {} does not start after the period before it", period)
+            }
+            LoadError::InvalidBreakRules { reason } => write!(f, "invalid break rules: {}", reason),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::Json(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 struct Depot {
     return_time: i32,
     x_coord: i32,
     y_coord: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct Patient {
     care_time: i32,
     demand: i32,
@@ -18,9 +118,72 @@ struct Patient {
     start_time: i32,
     x_coord: i32,
     y_coord: i32,
+
+    // qualifications the visiting nurse needs, ex "wound_care"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    required_skills: Vec<String>,
+
+    // [start, end] pairs for patients that can be visited at several times of the day, when given
+    // the start and end time should be the start of the first and the end of the last window
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    time_windows: Vec<(i32, i32)>,
+
+    // cost of not visiting the patient, patients without it have to be visited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    drop_penalty: Option<f32>,
+
+    // the care needs two nurses present at the same time, ex for a lift transfer
+    #[serde(default, skip_serializing_if = "is_false")]
+    synchronized: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    return !*value;
+}
+
+// every field falls back to the instance wide value when missing
+#[derive(Serialize, Deserialize)]
+struct Nurse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capacity: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_time: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    return_time: Option<i32>,
+
+    // 0 is the main depot, 1.. are the extra depots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depot: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skills: Vec<String>,
 }
 
-#[derive(Deserialize)]
+// travel times for trips leaving from the start time until the next period starts
+#[derive(Serialize, Deserialize)]
+struct TravelPeriod {
+    start_time: i32,
+    travel_times: Vec<Vec<f32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LunchBreakRule {
+    start_time: i32,
+    end_time: i32,
+    duration: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BreakRuleSet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lunch_break: Option<LunchBreakRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_continuous_work: Option<i32>,
+    #[serde(default)]
+    rest_duration: i32,
+}
+
+#[derive(Serialize, Deserialize)]
 struct TrainSet {
     instance_name: String,
     nbr_nurses: i32,
@@ -28,20 +191,186 @@ struct TrainSet {
     benchmark: f32,
 
     depot: Depot,
-    patients: HashMap<String, Patient>,
+
+    // care centres other than the main depot, their travel time rows come after the patients
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_depots: Vec<Depot>,
+
+    // ordered so saved instances are stable between runs
+    patients: BTreeMap<String, Patient>,
+
+    // optional per nurse limits, when missing every nurse uses capacity_nurse and the depot return time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nurses: Vec<Nurse>,
+
+    // some instances only have coordinates, the travel times are then the euclidean distances
+    #[serde(default)]
     travel_times: Vec<Vec<f32>>,
+
+    // optional time of day dependent travel times, travel_times is still used where the departure
+    // time is not known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    travel_time_periods: Vec<TravelPeriod>,
+
+    // optional labour rules shared by every nurse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    break_rules: Option<BreakRuleSet>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PatientPruned {
     pub travel_index: usize,
     pub care_time: i32,
     pub demand: i32,
+
+    // the start of the first and the end of the last time window
     pub end_time: i32,
     pub start_time: i32,
+
+    pub x_coord: i32,
+    pub y_coord: i32,
+
+    // bit flags into the skill names of the env
+    pub required_skills: u64,
+
+    // sorted and disjoint (start, end) pairs, the care has to be done inside one of them
+    pub time_windows: Vec<(i32, i32)>,
+
+    // added to the cost when the patient is left out, None for patients that have to be visited
+    pub drop_penalty: Option<f32>,
+
+    // two nurses have to start the care together, the second one is a SyncPartner stop
+    pub synchronized: bool,
 }
 
-#[derive(Debug)]
+impl PatientPruned {
+    /// The earliest time the care can start when arriving at the given time, this is the arrival
+    /// time or the opening of the first window the whole care fits inside. None if no window fits.
+    pub fn earliest_care_start(&self, arrival: f32) -> Option<f32> {
+        for (start, end) in &self.time_windows {
+            let care_start = arrival.max(*start as f32);
+            if care_start + self.care_time as f32 <= *end as f32 {
+                return Some(care_start);
+            }
+        }
+        return None;
+    }
+
+    /// The care start in the window that gives the least lateness when the care can not be done in
+    /// time, returns the care start and how many minutes after the window end the care is done
+    pub fn least_late_care_start(&self, arrival: f32) -> (f32, f32) {
+        let mut best = (arrival, f32::INFINITY);
+        for (start, end) in &self.time_windows {
+            let care_start = arrival.max(*start as f32);
+            let lateness = (care_start + self.care_time as f32 - *end as f32).max(0.0);
+            if lateness < best.1 {
+                best = (care_start, lateness);
+            }
+        }
+        return best;
+    }
+}
+
+/// A care centre the nurses start from and return to
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepotPruned {
+    pub travel_index: usize,
+    pub return_time: i32,
+    pub x_coord: i32,
+    pub y_coord: i32,
+}
+
+/// The limits of a single nurse, route k in a genome is driven by nurse k
+#[derive(Debug, Clone, PartialEq)]
+pub struct NursePruned {
+    pub capacity: i32,
+
+    // index into the depot list of the env
+    pub depot: usize,
+
+    // the nurse leaves the depot at the start time and has to be back by the return time
+    pub start_time: i32,
+    pub return_time: i32,
+
+    // bit flags into the skill names of the env
+    pub skills: u64,
+}
+
+pub const MAX_SKILLS: usize = 64;
+
+/// Turn a list of skill names into bit flags, new names are added to the known skills
+fn skill_mask(names: &Vec<String>, skill_names: &mut Vec<String>) -> Result<u64, LoadError> {
+    let mut mask: u64 = 0;
+    for name in names {
+        let bit = match skill_names.iter().position(|n| n == name) {
+            Some(bit) => bit,
+            None => {
+                skill_names.push(name.clone());
+                skill_names.len() - 1
+            }
+        };
+        if bit >= MAX_SKILLS {
+            return Err(LoadError::TooManySkills { found: skill_names.len() });
+        }
+        mask |= 1 << bit;
+    }
+    return Ok(mask);
+}
+
+/// Turn bit flags back into the skill names
+fn skill_names_from_mask(mask: u64, skill_names: &Vec<String>) -> Vec<String> {
+    return skill_names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| name.clone())
+        .collect();
+}
+
+fn break_rules_from_set(rules: &BreakRuleSet) -> Result<BreakRules, LoadError> {
+    let lunch_break = match &rules.lunch_break {
+        Some(lunch) => {
+            if lunch.duration < 0 || lunch.start_time + lunch.duration > lunch.end_time {
+                return Err(LoadError::InvalidBreakRules {
+                    reason: format!(
+                        "a lunch break of {} minutes does not fit between {} and {}",
+                        lunch.duration, lunch.start_time, lunch.end_time
+                    ),
+                });
+            }
+            Some(LunchBreak {
+                start_time: lunch.start_time,
+                end_time: lunch.end_time,
+                duration: lunch.duration,
+            })
+        }
+        None => None,
+    };
+    if rules.max_continuous_work.is_some() && rules.rest_duration <= 0 {
+        return Err(LoadError::InvalidBreakRules {
+            reason: String::from("max_continuous_work needs a rest_duration above 0"),
+        });
+    }
+    return Ok(BreakRules {
+        lunch_break,
+        max_continuous_work: rules.max_continuous_work,
+        rest_duration: rules.rest_duration,
+    });
+}
+
+/// A fleet where every nurse has the same limits, which is what the course instances use
+pub fn uniform_fleet(number_nurses: i32, capacity: i32, return_time: i32) -> Vec<NursePruned> {
+    let nurse = NursePruned {
+        capacity,
+        depot: 0,
+        start_time: 0,
+        return_time,
+        skills: 0,
+    };
+    return vec![nurse; number_nurses.max(0) as usize];
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct EnvPruned {
     pub set_name: String,
     pub number_nurses: i32,
@@ -51,44 +380,340 @@ pub struct EnvPruned {
 
     pub depo_ret_time: i32,
 
+    // the first depot is the main depot with travel index 0, the others are placed after the patients
+    pub depots: Vec<DepotPruned>,
+
+    // always has number_nurses entries, capacity_nurse and depo_ret_time are only the defaults
+    pub nurses: Vec<NursePruned>,
+
     pub patients: Vec<PatientPruned>,
 
+    // the names of the skill bits used by patients and nurses
+    pub skill_names: Vec<String>,
+
+    // not part of the instance file, set from the run config before evaluating
+    pub cost_model: CostModel,
+
     pub _travel_jump_size: i32,
     pub travel_matrix: Vec<f32>,
+
+    // sorted by start time, empty when the travel times does not depend on the time of day
+    pub travel_periods: Vec<TravelPeriodPruned>,
+
+    pub break_rules: BreakRules,
+}
+
+/// A flat travel matrix used for trips leaving between the start time and the next periods start.
+/// The first period is also used before its start and the last one until the end of the day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TravelPeriodPruned {
+    pub start_time: f32,
+    pub travel_matrix: Vec<f32>,
+}
+
+/// Check the size of a travel time matrix and flatten it
+fn flatten_travel_times(travel_times: &Vec<Vec<f32>>, num_stops: usize) -> Result<Vec<f32>, LoadError> {
+    if travel_times.len() != num_stops {
+        return Err(LoadError::TravelMatrixSize {
+            expected: num_stops,
+            found: travel_times.len(),
+        });
+    }
+    let mut travel_matrix: Vec<f32> = Vec::with_capacity(num_stops * num_stops);
+    for (row, travel_row) in travel_times.iter().enumerate() {
+        if travel_row.len() != num_stops {
+            return Err(LoadError::TravelMatrixRowSize {
+                row,
+                expected: num_stops,
+                found: travel_row.len(),
+            });
+        }
+        // the rows are already in travel index order
+        travel_matrix.extend_from_slice(travel_row);
+    }
+    return Ok(travel_matrix);
 }
 
 impl EnvPruned {
-    fn from_train_set(train_set: &TrainSet) -> EnvPruned {
+    fn from_train_set(train_set: &TrainSet) -> Result<EnvPruned, LoadError> {
         let num_patients = train_set.patients.len();
         let end_index = 1 + num_patients;
+        let num_stops = end_index + train_set.extra_depots.len();
+
+        let has_travel_times = !train_set.travel_times.is_empty();
+        let patients_travel_matrix = if has_travel_times {
+            flatten_travel_times(&train_set.travel_times, num_stops)?
+        } else {
+            Vec::new()
+        };
+
+        let mut travel_periods = Vec::with_capacity(train_set.travel_time_periods.len());
+        for (idx, period) in train_set.travel_time_periods.iter().enumerate() {
+            if idx > 0 && period.start_time <= train_set.travel_time_periods[idx - 1].start_time {
+                return Err(LoadError::UnsortedTravelPeriods { period: idx });
+            }
+            travel_periods.push(TravelPeriodPruned {
+                start_time: period.start_time as f32,
+                travel_matrix: flatten_travel_times(&period.travel_times, num_stops)?,
+            });
+        }
+
+        let break_rules = match &train_set.break_rules {
+            Some(rules) => break_rules_from_set(rules)?,
+            None => BreakRules::none(),
+        };
 
+        let mut depots = vec![DepotPruned {
+            travel_index: 0,
+            return_time: train_set.depot.return_time,
+            x_coord: train_set.depot.x_coord,
+            y_coord: train_set.depot.y_coord,
+        }];
+        for (idx, depot) in train_set.extra_depots.iter().enumerate() {
+            depots.push(DepotPruned {
+                travel_index: end_index + idx,
+                return_time: depot.return_time,
+                x_coord: depot.x_coord,
+                y_coord: depot.y_coord,
+            });
+        }
+
+        let mut skill_names: Vec<String> = Vec::new();
         let mut patients_list: Vec<PatientPruned> = Vec::new();
-        let mut patients_travel_matrix: Vec<f32> = Vec::with_capacity(num_patients);
-        patients_travel_matrix.append(train_set.travel_times.get(0).unwrap().clone().as_mut());
 
         for n in 1..end_index {
-            let patient = train_set.patients.get(&n.to_string()).unwrap();
+            let patient = match train_set.patients.get(&n.to_string()) {
+                Some(p) => p,
+                None => return Err(LoadError::NonContiguousPatientIds { missing_id: n }),
+            };
+            let time_windows = if patient.time_windows.is_empty() {
+                vec![(patient.start_time, patient.end_time)]
+            } else {
+                patient.time_windows.clone()
+            };
+            let windows_valid = time_windows.iter().all(|(start, end)| start <= end)
+                && time_windows.windows(2).all(|w| w[0].1 < w[1].0);
+            if !windows_valid {
+                return Err(LoadError::InvalidTimeWindows { patient_id: n });
+            }
+
             let pruned = PatientPruned {
                 travel_index: n,
                 care_time: patient.care_time,
                 demand: patient.demand,
-                end_time: patient.end_time,
-                start_time: patient.start_time,
+                end_time: time_windows.last().unwrap().1,
+                start_time: time_windows.first().unwrap().0,
+                x_coord: patient.x_coord,
+                y_coord: patient.y_coord,
+                required_skills: skill_mask(&patient.required_skills, &mut skill_names)?,
+                time_windows,
+                drop_penalty: patient.drop_penalty,
+                synchronized: patient.synchronized,
             };
             patients_list.push(pruned);
-
-            patients_travel_matrix.append(train_set.travel_times.get(n).unwrap().clone().as_mut());
         }
-        return EnvPruned {
-            set_name: (*train_set.instance_name.clone()).parse().unwrap(),
+        let nurses = if train_set.nurses.is_empty() {
+            uniform_fleet(train_set.nbr_nurses, train_set.capacity_nurse, train_set.depot.return_time)
+        } else if train_set.nurses.len() != train_set.nbr_nurses.max(0) as usize {
+            return Err(LoadError::NurseCount {
+                expected: train_set.nbr_nurses.max(0) as usize,
+                found: train_set.nurses.len(),
+            });
+        } else {
+            let mut nurses = Vec::with_capacity(train_set.nurses.len());
+            for (nurse_idx, n) in train_set.nurses.iter().enumerate() {
+                let depot = n.depot.unwrap_or(0);
+                if depot >= depots.len() {
+                    return Err(LoadError::UnknownDepot { nurse: nurse_idx, depot });
+                }
+                nurses.push(NursePruned {
+                    capacity: n.capacity.unwrap_or(train_set.capacity_nurse),
+                    depot,
+                    start_time: n.start_time.unwrap_or(0),
+                    return_time: n.return_time.unwrap_or(depots[depot].return_time),
+                    skills: skill_mask(&n.skills, &mut skill_names)?,
+                });
+            }
+            nurses
+        };
+
+        let mut env = EnvPruned {
+            set_name: train_set.instance_name.clone(),
             number_nurses: train_set.nbr_nurses,
             capacity_nurse: train_set.capacity_nurse,
             benchmark: train_set.benchmark,
             depo_ret_time: train_set.depot.return_time,
+            depots,
+            nurses,
             patients: patients_list,
+            skill_names,
+            cost_model: CostModel::default(),
             travel_matrix: patients_travel_matrix,
-            _travel_jump_size: (num_patients + 1) as i32,
+            _travel_jump_size: num_stops as i32,
+            travel_periods,
+            break_rules,
+        };
+        if !has_travel_times {
+            env.rebuild_travel_matrix_from_coords();
+        }
+        return Ok(env);
+    }
+
+    /// Replace the travel matrix with the euclidean distances between the depot and patient coordinates
+    pub fn rebuild_travel_matrix_from_coords(&mut self) {
+        let mut coords = vec![(0, 0); 1 + self.patients.len() + self.depots.len().saturating_sub(1)];
+        for depot in &self.depots {
+            coords[depot.travel_index] = (depot.x_coord, depot.y_coord);
+        }
+        for patient in &self.patients {
+            coords[patient.travel_index] = (patient.x_coord, patient.y_coord);
+        }
+        self.travel_matrix = euclidean_travel_times(&coords);
+        self._travel_jump_size = coords.len() as i32;
+    }
+
+    fn to_train_set(&self) -> TrainSet {
+        let mut patients = BTreeMap::new();
+        for (idx, patient) in self.patients.iter().enumerate() {
+            patients.insert(
+                (idx + 1).to_string(),
+                Patient {
+                    care_time: patient.care_time,
+                    demand: patient.demand,
+                    end_time: patient.end_time,
+                    start_time: patient.start_time,
+                    x_coord: patient.x_coord,
+                    y_coord: patient.y_coord,
+                    required_skills: skill_names_from_mask(patient.required_skills, &self.skill_names),
+                    time_windows: if patient.time_windows.len() > 1 {
+                        patient.time_windows.clone()
+                    } else {
+                        Vec::new()
+                    },
+                    drop_penalty: patient.drop_penalty,
+                    synchronized: patient.synchronized,
+                },
+            );
+        }
+
+        let jump_size = self._travel_jump_size as usize;
+        let travel_times = self.travel_matrix.chunks(jump_size).map(|row| row.to_vec()).collect();
+        let travel_time_periods = self
+            .travel_periods
+            .iter()
+            .map(|period| TravelPeriod {
+                start_time: period.start_time as i32,
+                travel_times: period.travel_matrix.chunks(jump_size).map(|row| row.to_vec()).collect(),
+            })
+            .collect();
+
+        // only write the nurses out when they differ from the defaults
+        let default_fleet = uniform_fleet(self.number_nurses, self.capacity_nurse, self.depo_ret_time);
+        let nurses = if self.nurses == default_fleet {
+            Vec::new()
+        } else {
+            self.nurses
+                .iter()
+                .map(|n| Nurse {
+                    capacity: Some(n.capacity),
+                    start_time: Some(n.start_time),
+                    return_time: Some(n.return_time),
+                    depot: Some(n.depot),
+                    skills: skill_names_from_mask(n.skills, &self.skill_names),
+                })
+                .collect()
         };
+
+        let mut depots: Vec<Depot> = self
+            .depots
+            .iter()
+            .map(|d| Depot {
+                return_time: d.return_time,
+                x_coord: d.x_coord,
+                y_coord: d.y_coord,
+            })
+            .collect();
+        let extra_depots = depots.split_off(1);
+
+        return TrainSet {
+            instance_name: self.set_name.clone(),
+            nbr_nurses: self.number_nurses,
+            capacity_nurse: self.capacity_nurse,
+            benchmark: self.benchmark,
+            depot: depots.pop().unwrap(),
+            extra_depots,
+            patients,
+            nurses,
+            travel_times,
+            travel_time_periods,
+            break_rules: if self.break_rules.is_empty() {
+                None
+            } else {
+                Some(BreakRuleSet {
+                    lunch_break: self.break_rules.lunch_break.as_ref().map(|lunch| LunchBreakRule {
+                        start_time: lunch.start_time,
+                        end_time: lunch.end_time,
+                        duration: lunch.duration,
+                    }),
+                    max_continuous_work: self.break_rules.max_continuous_work,
+                    rest_duration: self.break_rules.rest_duration,
+                })
+            },
+        };
+    }
+
+    /// Serialize the instance in the same json format as the train sets
+    pub fn to_json_string(&self) -> String {
+        return serde_json::to_string_pretty(&self.to_train_set()).unwrap();
+    }
+
+    /// Write the instance in the same json format as the train sets
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.to_train_set())?;
+        return Ok(());
+    }
+
+    /// Save the instance in the same json format as the train sets, it can be loaded again with
+    /// [load_train_set_from_path]
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        self.write_json(&mut writer)?;
+        return writer.flush();
+    }
+
+    /// Check if nurse k has every skill the patient needs
+    pub fn nurse_can_serve(&self, nurse_idx: usize, patient_id: i32) -> bool {
+        let required = self.patients.get((patient_id - 1) as usize).unwrap().required_skills;
+        let skills = match self.nurses.get(nurse_idx) {
+            Some(nurse) => nurse.skills,
+            None => 0,
+        };
+        return required & !skills == 0;
+    }
+
+    /// True if any patient needs a skill, instances without skills can skip the qualification checks
+    pub fn has_skill_requirements(&self) -> bool {
+        return self.patients.iter().any(|p| p.required_skills != 0);
+    }
+
+    /// True if some patients may be left out, the genome then has an extra segment after the last
+    /// nurse holding the dropped patients
+    pub fn allows_dropped_patients(&self) -> bool {
+        return self.patients.iter().any(|p| p.drop_penalty.is_some());
+    }
+
+    /// True if any patient needs two nurses at once, the evaluation then has to line up the routes
+    pub fn has_synchronized_patients(&self) -> bool {
+        return self.patients.iter().any(|p| p.synchronized);
+    }
+
+    /// The travel index nurse k starts and ends its route at
+    pub fn get_nurse_depot_index(&self, nurse_idx: usize) -> i32 {
+        let depot = match self.nurses.get(nurse_idx) {
+            Some(nurse) => nurse.depot,
+            None => 0,
+        };
+        return self.depots.get(depot).unwrap().travel_index as i32;
     }
 
     pub fn get_travel_time_between(&self, from: &i32, to: &i32) -> &f32 {
@@ -97,11 +722,348 @@ impl EnvPruned {
             .get(((from * self._travel_jump_size) + to) as usize)
             .unwrap();
     }
+
+    /// The travel time when leaving at the departure time.
+    ///
+    /// Each period gives the time the whole trip would take inside that period, the trip moves at
+    /// that pace until the period ends and continues at the pace of the next one. Leaving later can
+    /// then never arrive earlier (FIFO) even when the periods have very different travel times.
+    pub fn get_travel_time_between_at(&self, from: &i32, to: &i32, departure: f32) -> f32 {
+        if self.travel_periods.is_empty() {
+            return *self.get_travel_time_between(from, to);
+        }
+        let matrix_idx = ((from * self._travel_jump_size) + to) as usize;
+
+        let mut period_idx = self
+            .travel_periods
+            .iter()
+            .rposition(|p| p.start_time <= departure)
+            .unwrap_or(0);
+        let mut time = departure;
+        let mut remaining = 1.0;
+        loop {
+            let full_trip = self.travel_periods[period_idx].travel_matrix[matrix_idx];
+            let arrival = time + remaining * full_trip;
+            match self.travel_periods.get(period_idx + 1) {
+                Some(next) if full_trip > 0.0 && arrival > next.start_time => {
+                    // drive until the period ends and do the rest at the next periods pace
+                    remaining -= (next.start_time - time) / full_trip;
+                    time = next.start_time;
+                    period_idx += 1;
+                }
+                _ => return arrival - departure,
+            }
+        }
+    }
+
+    /// A lower bound on the travel time for any departure time
+    pub fn get_min_travel_time_between(&self, from: &i32, to: &i32) -> f32 {
+        if self.travel_periods.is_empty() {
+            return *self.get_travel_time_between(from, to);
+        }
+        let matrix_idx = ((from * self._travel_jump_size) + to) as usize;
+        return self
+            .travel_periods
+            .iter()
+            .map(|p| p.travel_matrix[matrix_idx])
+            .fold(f32::INFINITY, f32::min);
+    }
+}
+
+/// Where an instance is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstanceSource {
+    // one of the bundled ./dataset/train_{n}.json sets
+    TrainSet(i32),
+
+    // a file in the course json format
+    JsonFile(PathBuf),
+
+    // the course json format piped in on stdin
+    JsonStdin,
+
+    // a Solomon VRPTW file and the best known solution for it
+    SolomonFile { path: PathBuf, benchmark: f32 },
+
+    // a synthetic instance, the same config always gives the same instance
+    Generated(GeneratorConfig),
+}
+
+impl InstanceSource {
+    /// Read an instance from the command line: a train set number, a .json file, - for json on
+    /// stdin, generated:<c|r|rc>:<patients>:<seed> for a synthetic instance, anything else is taken
+    /// to be a Solomon file. The benchmark is only used for Solomon files, the json formats carry
+    /// their own.
+    pub fn parse(spec: &str, benchmark: f32) -> InstanceSource {
+        if let Ok(nmr) = spec.parse::<i32>() {
+            return InstanceSource::TrainSet(nmr);
+        }
+        if let Some(config) = GeneratorConfig::parse(spec) {
+            return InstanceSource::Generated(config);
+        }
+        if spec == "-" {
+            return InstanceSource::JsonStdin;
+        }
+        if spec.ends_with(".json") {
+            return InstanceSource::JsonFile(PathBuf::from(spec));
+        }
+        return InstanceSource::SolomonFile {
+            path: PathBuf::from(spec),
+            benchmark,
+        };
+    }
+
+    pub fn load(&self) -> Result<EnvPruned, LoadError> {
+        return match self {
+            InstanceSource::TrainSet(nmr) => try_get_train_sett(*nmr),
+            InstanceSource::JsonFile(path) => load_train_set_from_path(path),
+            InstanceSource::JsonStdin => load_train_set_from_reader(io::stdin().lock()),
+            InstanceSource::SolomonFile { path, benchmark } => get_solomon_set(path, *benchmark),
+            InstanceSource::Generated(config) => Ok(generate_instance(config)),
+        };
+    }
 }
 
+/// Load one of the bundled train sets, panics with the reason if the file is broken
 pub fn get_train_sett(nmr: i32) -> EnvPruned {
-    let json_str = fs::read_to_string(format!("./dataset/train_{}.json", nmr)).unwrap();
-    let train_s_raw: TrainSet = serde_json::from_str(json_str.as_str()).unwrap();
+    return match try_get_train_sett(nmr) {
+        Ok(env) => env,
+        Err(e) => panic!("could not load train set {}: {}", nmr, e),
+    };
+}
+
+pub fn try_get_train_sett(nmr: i32) -> Result<EnvPruned, LoadError> {
+    return load_train_set_from_path(format!("./dataset/train_{}.json", nmr));
+}
 
+/// Load an instance in the course json format from a file
+pub fn load_train_set_from_path<P: AsRef<Path>>(path: P) -> Result<EnvPruned, LoadError> {
+    let file = fs::File::open(path)?;
+    return load_train_set_from_reader(BufReader::new(file));
+}
+
+/// Load an instance in the course json format from anything readable, ex stdin or a socket
+pub fn load_train_set_from_reader<R: Read>(reader: R) -> Result<EnvPruned, LoadError> {
+    let train_s_raw: TrainSet = serde_json::from_reader(reader)?;
     return EnvPruned::from_train_set(&train_s_raw);
 }
+
+/// Load an instance in the course json format from a string
+pub fn load_train_set_from_str(json_str: &str) -> Result<EnvPruned, LoadError> {
+    let train_s_raw: TrainSet = serde_json::from_str(json_str)?;
+    return EnvPruned::from_train_set(&train_s_raw);
+}
+
+/// Build a flat travel matrix where the travel time between two stops is the euclidean distance
+/// between them, the first coordinate is the depot
+fn euclidean_travel_times(coords: &Vec<(i32, i32)>) -> Vec<f32> {
+    let mut travel_times = Vec::with_capacity(coords.len() * coords.len());
+    for (from_x, from_y) in coords {
+        for (to_x, to_y) in coords {
+            let dx = (from_x - to_x) as f32;
+            let dy = (from_y - to_y) as f32;
+            travel_times.push((dx * dx + dy * dy).sqrt());
+        }
+    }
+    return travel_times;
+}
+
+/// Parse the numbers of a whitespace separated line, solomon files sometimes store integers as floats
+fn parse_solomon_line(line_nmr: usize, line: &str, expected: usize) -> Result<Vec<i32>, LoadError> {
+    let mut values = Vec::with_capacity(expected);
+    for v in line.split_whitespace() {
+        match v.parse::<f32>() {
+            Ok(n) => values.push(n.round() as i32),
+            Err(_) => {
+                return Err(LoadError::Format {
+                    line: line_nmr,
+                    reason: format!("{:?} is not a number", v),
+                })
+            }
+        }
+    }
+    if values.len() != expected {
+        return Err(LoadError::Format {
+            line: line_nmr,
+            reason: format!("expected {} values, found {}", expected, values.len()),
+        });
+    }
+    return Ok(values);
+}
+
+/// Advance past the line starting with the given section header
+fn skip_to_section<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    section: &str,
+) -> Result<(), LoadError> {
+    return match lines.find(|(_, l)| l.starts_with(section)) {
+        Some(_) => Ok(()),
+        None => Err(LoadError::Format {
+            line: 0,
+            reason: format!("missing {} section", section),
+        }),
+    };
+}
+
+fn next_line<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<(usize, &'a str), LoadError> {
+    return lines.next().ok_or(LoadError::Format {
+        line: 0,
+        reason: String::from("unexpected end of file"),
+    });
+}
+
+/// Load a Solomon formatted VRPTW instance (C1/C2/R1/R2/RC1/RC2).
+///
+/// Customer 0 is the depot and its due date is used as the return time. The solomon due date is the
+/// latest start of service, so the patient end time is the due date plus the service time. The
+/// travel times are the euclidean distances between the coordinates. Solomon files does not contain
+/// a benchmark so the best known solution has to be passed in.
+pub fn get_solomon_set<P: AsRef<Path>>(path: P, benchmark: f32) -> Result<EnvPruned, LoadError> {
+    let text = fs::read_to_string(path)?;
+    return load_solomon_set_from_str(&text, benchmark);
+}
+
+/// Load a Solomon formatted instance from anything readable, see [get_solomon_set]
+pub fn load_solomon_set_from_reader<R: Read>(mut reader: R, benchmark: f32) -> Result<EnvPruned, LoadError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    return load_solomon_set_from_str(&text, benchmark);
+}
+
+/// Load a Solomon formatted instance from a string, see [get_solomon_set]
+pub fn load_solomon_set_from_str(text: &str, benchmark: f32) -> Result<EnvPruned, LoadError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(n, l)| (n + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty());
+
+    let instance_name = next_line(&mut lines)?.1.to_string();
+
+    // VEHICLE, NUMBER CAPACITY, values
+    skip_to_section(&mut lines, "VEHICLE")?;
+    next_line(&mut lines)?;
+    let (line_nmr, line) = next_line(&mut lines)?;
+    let vehicle = parse_solomon_line(line_nmr, line, 2)?;
+
+    // CUSTOMER, column headers, then one line per customer
+    skip_to_section(&mut lines, "CUSTOMER")?;
+    next_line(&mut lines)?;
+
+    let mut depot = Option::None;
+    let mut patients = BTreeMap::new();
+    for (line_nmr, line) in lines {
+        // cust no, x, y, demand, ready time, due date, service time
+        let v = parse_solomon_line(line_nmr, line, 7)?;
+        if v[0] == 0 {
+            depot = Some(Depot {
+                return_time: v[5],
+                x_coord: v[1],
+                y_coord: v[2],
+            });
+        } else {
+            patients.insert(
+                v[0].to_string(),
+                Patient {
+                    care_time: v[6],
+                    demand: v[3],
+                    end_time: v[5] + v[6],
+                    start_time: v[4],
+                    x_coord: v[1],
+                    y_coord: v[2],
+                    required_skills: Vec::new(),
+                    time_windows: Vec::new(),
+                    drop_penalty: None,
+                    synchronized: false,
+                },
+            );
+        }
+    }
+
+    let train_set = TrainSet {
+        instance_name,
+        nbr_nurses: vehicle[0],
+        capacity_nurse: vehicle[1],
+        benchmark,
+        depot: depot.ok_or(LoadError::Format {
+            line: 0,
+            reason: String::from("customer 0 (the depot) is missing"),
+        })?,
+        extra_depots: Vec::new(),
+        patients,
+        nurses: Vec::new(),
+        travel_times: Vec::new(),
+        travel_time_periods: Vec::new(),
+        break_rules: None,
+    };
+    return EnvPruned::from_train_set(&train_set);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance_generator::SpatialDistribution;
+
+    #[test]
+    fn saved_train_sets_load_back_the_same() {
+        for nmr in 0..10 {
+            let env = get_train_sett(nmr);
+            let loaded = load_train_set_from_str(&env.to_json_string()).unwrap();
+            assert_eq!(env, loaded, "train set {}", nmr);
+
+            let mut written = Vec::new();
+            env.write_json(&mut written).unwrap();
+            assert_eq!(env, load_train_set_from_reader(written.as_slice()).unwrap(), "train set {}", nmr);
+        }
+    }
+
+    #[test]
+    fn generated_specs_load_the_generated_instance() {
+        let instance = InstanceSource::parse("generated:rc:50:3", 0.0);
+        let config = GeneratorConfig::new(3, 50, SpatialDistribution::from_class("rc").unwrap());
+        assert_eq!(instance, InstanceSource::Generated(config.clone()));
+        assert_eq!(instance.load().unwrap(), generate_instance(&config));
+
+        // a misspelled class is not a generator config
+        assert!(matches!(InstanceSource::parse("generated:x:50:3", 0.0), InstanceSource::SolomonFile { .. }));
+    }
+
+    /// Train set 0 where trips get three times slower at 300 and twice as fast at 500
+    fn rush_hour_env() -> EnvPruned {
+        let mut env = get_train_sett(0);
+        env.travel_periods = [(0, 1.0), (300, 3.0), (500, 0.5)]
+            .iter()
+            .map(|(start_time, pace)| TravelPeriodPruned {
+                start_time: *start_time as f32,
+                travel_matrix: env.travel_matrix.iter().map(|t| t * pace).collect(),
+            })
+            .collect();
+        return env;
+    }
+
+    #[test]
+    fn leaving_later_never_arrives_earlier() {
+        let env = rush_hour_env();
+        for (from, to) in [(0, 1), (1, 0), (5, 42), (17, 3)] {
+            let mut last_arrival = f32::MIN;
+            for step in 0..1600 {
+                let departure = step as f32 * 0.5;
+                let arrival = departure + env.get_travel_time_between_at(&from, &to, departure);
+                assert!(arrival >= last_arrival - 1e-3, "{} to {} leaving at {}", from, to, departure);
+                last_arrival = arrival;
+            }
+        }
+
+        // a trip that runs into the slow period takes longer than the same trip before it
+        let trip = *env.get_travel_time_between(&5, &42);
+        assert!(env.get_travel_time_between_at(&5, &42, 300.0 - trip / 2.0) > trip);
+    }
+
+    #[test]
+    fn zero_length_trips_take_no_time() {
+        let env = rush_hour_env();
+        for departure in [0.0, 299.5, 300.0, 450.0, 500.0, 900.0] {
+            assert_eq!(env.get_travel_time_between_at(&7, &7, departure), 0.0);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::train_data_parsing::EnvPruned;
+
+/// A reason a solution is not correct
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationIssue {
+    // the id is not a patient of the instance
+    UnknownPatient { route: usize, patient_id: i32 },
+
+    // the patient is on more routes, or more times on a route, than it should be
+    DuplicateVisit { patient_id: i32, visits: usize },
+
+    // a patient that has to be visited is on no route
+    MissingPatient { patient_id: i32 },
+
+    // a patient that needs two nurses got this many nurses
+    MissingPartner { patient_id: i32, nurses: usize },
+
+    // more routes with patients than the instance has nurses
+    TooManyRoutes { used: usize, allowed: i32 },
+
+    // a route with patients that no nurse can drive
+    RouteWithoutNurse { route: usize },
+
+    // the care ended after the window that was open or next to open on arrival
+    TimeWindow { route: usize, patient_id: i32, care_end: f32, window_end: i32 },
+
+    Capacity { route: usize, load: i32, capacity: i32 },
+    ReturnTime { route: usize, return_time: f32, limit: i32 },
+    MissingSkills { route: usize, patient_id: i32 },
+}
+
+impl Display for VerificationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        return match self {
+            VerificationIssue::UnknownPatient { route, patient_id } => {
+                write!(f, "route {}: {} is not a patient", route, patient_id)
+            }
+            VerificationIssue::DuplicateVisit { patient_id, visits } => {
+                write!(f, "patient {} is visited {} times", patient_id, visits)
+            }
+            VerificationIssue::MissingPatient { patient_id } => write!(f, "patient {} is not visited", patient_id),
+            VerificationIssue::MissingPartner { patient_id, nurses } => {
+                write!(f, "patient {} needs two nurses but got {}", patient_id, nurses)
+            }
+            VerificationIssue::TooManyRoutes { used, allowed } => {
+                write!(f, "{} routes are used but there are {} nurses", used, allowed)
+            }
+            VerificationIssue::RouteWithoutNurse { route } => write!(f, "route {} has no nurse", route),
+            VerificationIssue::TimeWindow { route, patient_id, care_end, window_end } => write!(
+                f,
+                "route {}: care for patient {} ends at {:.2}, the window closes at {}",
+                route, patient_id, care_end, window_end
+            ),
+            VerificationIssue::Capacity { route, load, capacity } => {
+                write!(f, "route {}: load {} above the capacity {}", route, load, capacity)
+            }
+            VerificationIssue::ReturnTime { route, return_time, limit } => {
+                write!(f, "route {}: back at {:.2}, has to be back by {}", route, return_time, limit)
+            }
+            VerificationIssue::MissingSkills { route, patient_id } => {
+                write!(f, "route {}: the nurse is not qualified for patient {}", route, patient_id)
+            }
+        };
+    }
+}
+
+/// The recomputed numbers for one route
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteCheck {
+    pub route: usize,
+    pub travel_time: f32,
+    pub return_time: f32,
+    pub load: i32,
+}
+
+/// The result of checking a solution against the instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationReport {
+    pub routes: Vec<RouteCheck>,
+    pub total_travel_time: f32,
+
+    // optional patients that are on no route
+    pub dropped_patients: Vec<i32>,
+
+    pub issues: Vec<VerificationIssue>,
+}
+
+impl VerificationReport {
+    pub fn passed(&self) -> bool {
+        return self.issues.is_empty();
+    }
+}
+
+impl Display for VerificationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: travel time {:.2}, {} routes used, {} issues",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.total_travel_time,
+            self.routes.len(),
+            self.issues.len()
+        )?;
+        if !self.dropped_patients.is_empty() {
+            writeln!(f, "  dropped {:?}", self.dropped_patients)?;
+        }
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Read routes written as a list of patient id lists, ex the output of
+/// [crate::genalg::Genotype::get_as_delivery_str]
+pub fn parse_routes(text: &str) -> Result<Vec<Vec<i32>>, serde_json::Error> {
+    return serde_json::from_str(text.trim());
+}
+
+/// Read every solution in a text where each solution is one line starting with [[, the other lines
+/// are skipped so a score log like scrs.txt can be checked as it is
+pub fn parse_route_lines(text: &str) -> Result<Vec<Vec<Vec<i32>>>, serde_json::Error> {
+    return text.lines().filter(|line| line.trim_start().starts_with("[[")).map(parse_routes).collect();
+}
+
+/// Drive a single route from the nurse's depot and check its windows, load, skills and return time.
+/// Carried holds the synchronized patients whose demand an earlier visit already counted.
+fn check_route(
+    env: &EnvPruned,
+    route_idx: usize,
+    route: &Vec<i32>,
+    carried: &mut HashSet<i32>,
+    issues: &mut Vec<VerificationIssue>,
+) -> RouteCheck {
+    let nurse = env.nurses.get(route_idx).unwrap();
+    let depot = env.depots.get(nurse.depot).unwrap().travel_index as i32;
+
+    let mut time = nurse.start_time as f32;
+    let mut travel_time = 0.0;
+    let mut load = 0;
+    let mut location = depot;
+
+    for patient_id in route {
+        let patient = env.patients.get((patient_id - 1) as usize).unwrap();
+        let next = patient.travel_index as i32;
+        let leg = env.get_travel_time_between_at(&location, &next, time);
+        travel_time += leg;
+        time += leg;
+        location = next;
+
+        // the first window the whole care fits in, the nurse waits for it to open
+        let care_time = patient.care_time as f32;
+        let in_window = patient
+            .time_windows
+            .iter()
+            .map(|(start, end)| (time.max(*start as f32), *end))
+            .find(|(care_start, end)| care_start + care_time <= *end as f32);
+        match in_window {
+            Some((care_start, _)) => time = care_start + care_time,
+            None => {
+                // report against the window that was open or next to open on arrival
+                let (start, window_end) = *patient
+                    .time_windows
+                    .iter()
+                    .find(|(_, end)| time <= *end as f32)
+                    .unwrap_or(patient.time_windows.last().unwrap());
+                time = time.max(start as f32) + care_time;
+                issues.push(VerificationIssue::TimeWindow {
+                    route: route_idx,
+                    patient_id: *patient_id,
+                    care_end: time,
+                    window_end,
+                });
+            }
+        }
+
+        if !patient.synchronized || carried.insert(*patient_id) {
+            load += patient.demand;
+        }
+        if patient.required_skills & !nurse.skills != 0 {
+            issues.push(VerificationIssue::MissingSkills { route: route_idx, patient_id: *patient_id });
+        }
+    }
+
+    let leg = env.get_travel_time_between_at(&location, &depot, time);
+    travel_time += leg;
+    time += leg;
+
+    if load > nurse.capacity {
+        issues.push(VerificationIssue::Capacity { route: route_idx, load, capacity: nurse.capacity });
+    }
+    if time > nurse.return_time as f32 {
+        issues.push(VerificationIssue::ReturnTime { route: route_idx, return_time: time, limit: nurse.return_time });
+    }
+
+    return RouteCheck { route: route_idx, travel_time, return_time: time, load };
+}
+
+/// Check a solution given as one list of patient ids per nurse, route k is driven by nurse k.
+///
+/// This is written apart from the ga evaluation on purpose and knows nothing about penalties. It
+/// checks that every patient is visited once (twice by two nurses for synchronized patients,
+/// optional patients may be left out), the number of routes, and the windows, capacity, skills and
+/// return time of every route. The demand of a synchronized patient is counted once, on the first
+/// route that lists it, the same as the ga evaluation. Routes past the last nurse are reported and
+/// their patients do not count as visited, so a pool of dropped patients written out as an extra
+/// route is caught. Break rules and synchronized start times are not checked.
+pub fn verify_routes(env: &EnvPruned, routes: &Vec<Vec<i32>>) -> VerificationReport {
+    let mut issues = Vec::new();
+
+    // which routes every patient is on
+    let mut visits: Vec<Vec<usize>> = vec![Vec::new(); env.patients.len()];
+    for (route_idx, route) in routes.iter().enumerate().take(env.nurses.len()) {
+        for patient_id in route {
+            if *patient_id < 1 || *patient_id as usize > env.patients.len() {
+                issues.push(VerificationIssue::UnknownPatient { route: route_idx, patient_id: *patient_id });
+                continue;
+            }
+            visits[(*patient_id - 1) as usize].push(route_idx);
+        }
+    }
+
+    let mut dropped_patients = Vec::new();
+    for (idx, routes_of_patient) in visits.iter().enumerate() {
+        let patient_id = (idx + 1) as i32;
+        let patient = env.patients.get(idx).unwrap();
+        if routes_of_patient.is_empty() {
+            if patient.drop_penalty.is_some() {
+                dropped_patients.push(patient_id);
+            } else {
+                issues.push(VerificationIssue::MissingPatient { patient_id });
+            }
+            continue;
+        }
+        let expected = if patient.synchronized { 2 } else { 1 };
+        if routes_of_patient.len() > expected {
+            issues.push(VerificationIssue::DuplicateVisit { patient_id, visits: routes_of_patient.len() });
+        }
+        if patient.synchronized {
+            let mut nurses = routes_of_patient.clone();
+            nurses.dedup();
+            if nurses.len() != 2 {
+                issues.push(VerificationIssue::MissingPartner { patient_id, nurses: nurses.len() });
+            }
+        }
+    }
+
+    let used = routes.iter().filter(|r| !r.is_empty()).count();
+    if used > env.number_nurses as usize {
+        issues.push(VerificationIssue::TooManyRoutes { used, allowed: env.number_nurses });
+    }
+
+    // the ids have to be known before a route can be driven
+    let ids_known = !issues.iter().any(|i| matches!(i, VerificationIssue::UnknownPatient { .. }));
+    let mut carried = HashSet::new();
+    let mut checks = Vec::new();
+    for (route_idx, route) in routes.iter().enumerate() {
+        if route.is_empty() {
+            continue;
+        }
+        if route_idx >= env.nurses.len() {
+            issues.push(VerificationIssue::RouteWithoutNurse { route: route_idx });
+            continue;
+        }
+        if ids_known {
+            checks.push(check_route(env, route_idx, route, &mut carried, &mut issues));
+        }
+    }
+
+    return VerificationReport {
+        total_travel_time: checks.iter().map(|c| c.travel_time).sum(),
+        routes: checks,
+        dropped_patients,
+        issues,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genalg::{evaluate, generate_random_genome, Genotype, NurseStop};
+    use crate::genalg::tests::{genome_with_dropped, optional_patients_env};
+    use crate::mutation::MetaGenes;
+    use crate::train_data_parsing::get_train_sett;
+
+    /// The verifier and the ga have to agree on the loads, the travel time and the dropped patients
+    fn assert_agrees(env: &EnvPruned, genome: &Genotype) {
+        let report = evaluate(env, genome);
+        let verified = verify_routes(env, &genome.get_as_delivery_str(env));
+
+        for check in &verified.routes {
+            let route = report.routes.get(check.route).unwrap();
+            assert_eq!(check.load, route.load, "route {}", check.route);
+            assert!((check.travel_time - route.travel_time).abs() < 1e-2, "route {}", check.route);
+        }
+        let mut dropped = genome.get_dropped_patients(env);
+        dropped.sort();
+        assert_eq!(verified.dropped_patients, dropped);
+    }
+
+    /// The genome with the routes in order, one nurse each
+    fn genome_of(routes: &[Vec<i32>]) -> Genotype {
+        let stops = routes
+            .iter()
+            .map(|route| route.iter().map(|id| NurseStop::Patient(*id)).collect::<Vec<NurseStop>>())
+            .collect::<Vec<Vec<NurseStop>>>()
+            .join(&NurseStop::Depot);
+        return Genotype::new(stops, MetaGenes::new());
+    }
+
+    #[test]
+    fn ga_genomes_pass_the_same_checks() {
+        let env = get_train_sett(0);
+        for genome in generate_random_genome(&env, 20) {
+            assert_agrees(&env, &genome);
+            assert_eq!(verify_routes(&env, &genome.get_as_delivery_str(&env)).passed(), evaluate(&env, &genome).is_valid());
+        }
+
+        // the first solution logged for train set 9 is valid for both
+        let env = get_train_sett(9);
+        let mut routes = parse_route_lines(include_str!("../scrs.txt")).unwrap().remove(0);
+        let genome = genome_of(&routes);
+        assert_agrees(&env, &genome);
+        assert!(verify_routes(&env, &routes).passed());
+        assert!(evaluate(&env, &genome).is_valid());
+
+        // leaving out the last patient of a route breaks nothing else
+        let route = routes.iter_mut().find(|route| !route.is_empty()).unwrap();
+        let patient_id = route.pop().unwrap();
+        assert_eq!(verify_routes(&env, &routes).issues, vec![VerificationIssue::MissingPatient { patient_id }]);
+    }
+
+    #[test]
+    fn synchronized_demand_is_counted_once() {
+        let mut env = get_train_sett(0);
+        for patient in env.patients.iter_mut().step_by(10) {
+            patient.synchronized = true;
+        }
+        for genome in generate_random_genome(&env, 20) {
+            assert_agrees(&env, &genome);
+        }
+    }
+
+    #[test]
+    fn dropped_patients_are_not_visited() {
+        let env = optional_patients_env();
+        for _ in 0..20 {
+            assert_agrees(&env, &genome_with_dropped(&env, 5));
+        }
+
+        // the pool written out as one more route
+        let genome = genome_with_dropped(&env, 1);
+        let mut routes = genome.get_as_delivery_str(&env);
+        routes.push(genome.get_dropped_patients(&env));
+        let verified = verify_routes(&env, &routes);
+        assert_eq!(verified.dropped_patients, genome.get_dropped_patients(&env));
+        assert!(verified.issues.contains(&VerificationIssue::RouteWithoutNurse { route: env.nurses.len() }));
+    }
+
+    #[test]
+    fn score_logs_give_one_solution_per_route_line() {
+        let text = "task 9\n849\n[[], [2, 1]]\n\n843\n[[1], [2]]\n";
+        let solutions = parse_route_lines(text).unwrap();
+        assert_eq!(solutions, vec![vec![vec![], vec![2, 1]], vec![vec![1], vec![2]]]);
+    }
+}